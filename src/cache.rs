@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::matching;
+use crate::mbzlists::Track;
+
+const POSITIVE_TTL: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+// Misses are retried sooner since the platform catalog might have changed
+const NEGATIVE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Base directory for all local caches. `MBZR_CACHE_DIR` overrides the
+/// platform specific user cache directory.
+pub fn base_dir() -> Option<PathBuf> {
+    match std::env::var("MBZR_CACHE_DIR") {
+        Ok(dir) => Some(PathBuf::from(dir)),
+        Err(_) => dirs::cache_dir().map(|dir| dir.join("mbzlists-resolvers")),
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub platform: String,
+    pub key: String,
+    /// Id of the resolved item on the platform, `None` for a cached miss
+    pub platform_id: Option<String>,
+    /// Unix timestamp (seconds) of when the resolution happened
+    pub resolved_at: i64,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        let ttl = if self.platform_id.is_some() { POSITIVE_TTL } else { NEGATIVE_TTL };
+        now().saturating_sub(self.resolved_at) <= ttl.as_secs() as i64
+    }
+}
+
+/// On-disk cache of track resolutions shared across runs and platforms
+pub struct ResolutionCache {
+    conn: Connection,
+}
+
+impl ResolutionCache {
+    pub fn open(path: &Path) -> Result<ResolutionCache> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path).with_context(|| format!("Unable to open cache at {:?}", path))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS resolutions (
+                platform TEXT NOT NULL,
+                key TEXT NOT NULL,
+                platform_id TEXT,
+                resolved_at INTEGER NOT NULL,
+                PRIMARY KEY (platform, key)
+            )"
+        )?;
+
+        Ok(ResolutionCache { conn })
+    }
+
+    pub fn open_default() -> Result<ResolutionCache> {
+        let dir = base_dir().context("Unable to find a cache directory, set MBZR_CACHE_DIR")?;
+        Self::open(&dir.join("resolutions.sqlite"))
+    }
+
+    /// Cache key for a track. Recording MBIDs are preferred since they are
+    /// stable across spelling differences.
+    pub fn key_for(track: &Track) -> String {
+        match track.recording_mbid() {
+            Some(mbid) => format!("mbid:{mbid}"),
+            None => format!("text:{}|{}", matching::normalize(&track.title), matching::normalize(&track.creator)),
+        }
+    }
+
+    /// Return a fresh entry for the track, if any. Expired entries are ignored.
+    pub fn get(&self, platform: &str, track: &Track) -> Result<Option<CacheEntry>> {
+        let entry = self.conn.query_row(
+            "SELECT platform, key, platform_id, resolved_at FROM resolutions WHERE platform = ?1 AND key = ?2",
+            params![platform, Self::key_for(track)],
+            row_to_entry,
+        ).optional()?;
+
+        Ok(entry.filter(|e| e.is_fresh()))
+    }
+
+    /// Save a resolution. Pass `None` as `platform_id` to record a miss.
+    pub fn put(&self, platform: &str, track: &Track, platform_id: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO resolutions (platform, key, platform_id, resolved_at) VALUES (?1, ?2, ?3, ?4)",
+            params![platform, Self::key_for(track), platform_id, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn entries(&self, platform: Option<&str>) -> Result<Vec<CacheEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT platform, key, platform_id, resolved_at FROM resolutions
+             WHERE ?1 IS NULL OR platform = ?1 ORDER BY platform, key"
+        )?;
+        let entries = stmt.query_map(params![platform], row_to_entry)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    /// Delete entries, optionally only for one platform or only the expired
+    /// ones. Returns the number of deleted entries.
+    pub fn purge(&self, platform: Option<&str>, expired_only: bool) -> Result<usize> {
+        let now = now();
+        let deleted = self.conn.execute(
+            "DELETE FROM resolutions WHERE (?1 IS NULL OR platform = ?1) AND (NOT ?2
+             OR resolved_at < CASE WHEN platform_id IS NULL THEN ?3 ELSE ?4 END)",
+            params![platform, expired_only, now - NEGATIVE_TTL.as_secs() as i64, now - POSITIVE_TTL.as_secs() as i64],
+        )?;
+        Ok(deleted)
+    }
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<CacheEntry> {
+    Ok(CacheEntry {
+        platform: row.get(0)?,
+        key: row.get(1)?,
+        platform_id: row.get(2)?,
+        resolved_at: row.get(3)?,
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, creator: &str, mbid: Option<&str>) -> Track {
+        Track {
+            title: title.to_string(),
+            creator: creator.to_string(),
+            identifiers: mbid.map(|mbid| format!("https://musicbrainz.org/recording/{mbid}")).into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn open_temp(name: &str) -> (ResolutionCache, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("mbzlists-cache-{name}-{}", std::process::id()));
+        (ResolutionCache::open(&dir.join("resolutions.sqlite")).unwrap(), dir)
+    }
+
+    // Move an entry back in time by `age`
+    fn age(cache: &ResolutionCache, platform: &str, track: &Track, age: Duration) {
+        cache.conn.execute(
+            "UPDATE resolutions SET resolved_at = ?1 WHERE platform = ?2 AND key = ?3",
+            params![now() - age.as_secs() as i64, platform, ResolutionCache::key_for(track)],
+        ).unwrap();
+    }
+
+    #[test]
+    fn keys_tracks() {
+        assert_eq!(ResolutionCache::key_for(&track("Halo", "Beyoncé", Some("abc-123"))), "mbid:abc-123");
+        assert_eq!(ResolutionCache::key_for(&track("Halo!", "Beyoncé", None)), "text:halo|beyonce");
+        assert_eq!(
+            ResolutionCache::key_for(&track("  HALO ", "beyonce", None)),
+            ResolutionCache::key_for(&track("Halo", "Beyoncé", None)),
+        );
+    }
+
+    #[test]
+    fn expires_entries() {
+        let (cache, dir) = open_temp("expiry");
+        let (hit, miss) = (track("Halo", "Beyoncé", Some("abc-123")), track("Missing", "Nobody", None));
+
+        cache.put("subsonic", &hit, Some("ss-1")).unwrap();
+        cache.put("subsonic", &miss, None).unwrap();
+        assert_eq!(cache.get("subsonic", &hit).unwrap().unwrap().platform_id.as_deref(), Some("ss-1"));
+        assert_eq!(cache.get("subsonic", &miss).unwrap().unwrap().platform_id, None);
+        assert!(cache.get("plex", &hit).unwrap().is_none());
+
+        // Misses expire before hits do
+        let between = NEGATIVE_TTL + Duration::from_secs(60);
+        age(&cache, "subsonic", &hit, between);
+        age(&cache, "subsonic", &miss, between);
+        assert!(cache.get("subsonic", &hit).unwrap().is_some());
+        assert!(cache.get("subsonic", &miss).unwrap().is_none());
+
+        age(&cache, "subsonic", &hit, POSITIVE_TTL + Duration::from_secs(60));
+        assert!(cache.get("subsonic", &hit).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn purges_entries() {
+        let (cache, dir) = open_temp("purge");
+        let tracks = [track("A", "X", None), track("B", "X", None), track("C", "X", None)];
+
+        cache.put("subsonic", &tracks[0], Some("ss-1")).unwrap();
+        cache.put("subsonic", &tracks[1], None).unwrap();
+        cache.put("subsonic", &tracks[2], Some("ss-3")).unwrap();
+        cache.put("plex", &tracks[1], None).unwrap();
+        age(&cache, "subsonic", &tracks[1], NEGATIVE_TTL + Duration::from_secs(60));
+        age(&cache, "plex", &tracks[1], NEGATIVE_TTL + Duration::from_secs(60));
+        age(&cache, "subsonic", &tracks[2], NEGATIVE_TTL + Duration::from_secs(60));
+
+        assert_eq!(cache.purge(Some("subsonic"), true).unwrap(), 1);
+        let keys = cache.entries(None).unwrap().into_iter().map(|e| (e.platform, e.key)).collect::<Vec<_>>();
+        assert_eq!(keys, vec![
+            ("plex".to_string(), "text:b|x".to_string()),
+            ("subsonic".to_string(), "text:a|x".to_string()),
+            ("subsonic".to_string(), "text:c|x".to_string()),
+        ]);
+
+        assert_eq!(cache.purge(Some("subsonic"), false).unwrap(), 2);
+        assert_eq!(cache.purge(None, false).unwrap(), 1);
+        assert!(cache.entries(None).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use log::{debug, info};
+
+use crate::matching;
+use crate::mbzlists::{Playlist, Track, Tracklist};
+use crate::musicbrainz::{MusicBrainzClient, Recording};
+
+/// A track as read from a platform playlist
+#[derive(Debug, Default)]
+pub struct SourceTrack {
+    pub title: String,
+    pub artist: String,
+    pub isrcs: Vec<String>,
+    pub recording_mbid: Option<String>,
+}
+
+/// A playlist read from a platform
+#[derive(Debug)]
+pub struct SourcePlaylist {
+    pub name: String,
+    pub tracks: Vec<SourceTrack>,
+}
+
+/// Convert to a mbzlists track. Recordings are looked up on MusicBrainz by
+/// ISRC when the platform has no MBID, and artist MBIDs are taken from the
+/// recording.
+fn to_track(source: SourceTrack, mb_client: &MusicBrainzClient) -> Track {
+    let recording = match &source.recording_mbid {
+        Some(mbid) => mb_client.recording(mbid)
+            .inspect_err(|err| debug!("Recording lookup failed for {mbid}: {err}"))
+            .ok(),
+        None => source.isrcs.iter()
+            .filter_map(|isrc| recording_by_isrc(mb_client, isrc, &source.title).ok().flatten())
+            .next(),
+    };
+
+    let mut identifiers = vec![];
+    if let Some(mbid) = source.recording_mbid.clone().or(recording.as_ref().map(|r| r.id.clone())) {
+        identifiers.push(format!("https://musicbrainz.org/recording/{mbid}"));
+    }
+    if let Some(recording) = &recording {
+        identifiers.extend(recording.artist_credit.iter().map(|c| format!("https://musicbrainz.org/artist/{}", c.artist.id)));
+    }
+    identifiers.extend(source.isrcs.iter().map(|isrc| format!("urn:isrc:{isrc}")));
+
+    Track { title: source.title, creator: source.artist, identifiers, ..Default::default() }
+}
+
+/// Convert a platform playlist to a mbzlists playlist, ready to be written as
+/// XSPF.
+pub fn to_playlist(source: SourcePlaylist) -> Result<Playlist> {
+    let mb_client = MusicBrainzClient::from_env()?;
+    info!("Read total {} tracks from {}", source.tracks.len(), source.name);
+
+    let tracks = source.tracks.into_iter().map(|track| to_track(track, &mb_client)).collect::<Vec<Track>>();
+    info!("Found recording MBIDs for {} tracks", tracks.iter().filter(|t| t.recording_mbid().is_some()).count());
+
+    Ok(Playlist { title: source.name, tracklist: Tracklist { tracks }, ..Default::default() })
+}
+
+// The recording with the given ISRC, preferring one with a matching title
+// since ISRCs are sometimes shared by several recordings.
+fn recording_by_isrc(mb_client: &MusicBrainzClient, isrc: &str, title: &str) -> Result<Option<Recording>> {
+    let mut recordings = mb_client.recordings_by_isrc(isrc)?;
+    let index = recordings.iter().position(|r| matching::title_matches(&r.title, title)).unwrap_or(0);
+    Ok(if recordings.is_empty() { None } else { Some(recordings.swap_remove(index)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_LUCKY: &str = "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae";
+    const GET_LUCKY_EDIT: &str = "0383dadf-2a4e-4d10-a46a-e9e041da8eb3";
+    const DAFT_PUNK: &str = "056e4f3e-d505-4dad-8ec1-d04f521cbb56";
+
+    fn recording(mbid: &str, title: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": mbid,
+            "title": title,
+            "artist-credit": [{ "name": "Daft Punk", "joinphrase": "", "artist": { "id": DAFT_PUNK, "name": "Daft Punk" } }],
+        })
+    }
+
+    // Client answering only from cached responses
+    fn mb_client(dir: &std::path::Path) -> MusicBrainzClient {
+        let write = |entity: &str, id: &str, body: serde_json::Value| {
+            std::fs::create_dir_all(dir.join(entity)).unwrap();
+            std::fs::write(dir.join(entity).join(format!("{id}.json")), body.to_string()).unwrap();
+        };
+        write("isrc", "GBDUW0000059", serde_json::json!({ "recordings": [recording(GET_LUCKY_EDIT, "Get Lucky (radio edit)"), recording(GET_LUCKY, "Get Lucky")] }));
+        write("isrc", "USQX91300108", serde_json::json!({ "recordings": [recording(GET_LUCKY_EDIT, "Get Lucky (radio edit)")] }));
+        write("isrc", "USQX91300109", serde_json::json!({ "recordings": [] }));
+        write("recording", GET_LUCKY, recording(GET_LUCKY, "Get Lucky"));
+
+        MusicBrainzClient::new("http://127.0.0.1:9".to_string(), Some(dir.to_path_buf())).unwrap()
+    }
+
+    fn source(isrcs: &[&str], recording_mbid: Option<&str>) -> SourceTrack {
+        SourceTrack {
+            title: "Get Lucky".to_string(),
+            artist: "Daft Punk".to_string(),
+            isrcs: isrcs.iter().map(|isrc| isrc.to_string()).collect(),
+            recording_mbid: recording_mbid.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn converts_tracks() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-export-{}", std::process::id()));
+        let mb_client = mb_client(&dir);
+        let identifiers = |source| to_track(source, &mb_client).identifiers;
+
+        // The recording with a matching title wins among the ISRC's ones
+        assert_eq!(identifiers(source(&["GBDUW0000059"], None)), vec![
+            format!("https://musicbrainz.org/recording/{GET_LUCKY}"),
+            format!("https://musicbrainz.org/artist/{DAFT_PUNK}"),
+            "urn:isrc:GBDUW0000059".to_string(),
+        ]);
+
+        // Else the first one, after skipping ISRCs without recordings
+        assert_eq!(identifiers(source(&["USQX91300109", "USQX91300108"], None)), vec![
+            format!("https://musicbrainz.org/recording/{GET_LUCKY_EDIT}"),
+            format!("https://musicbrainz.org/artist/{DAFT_PUNK}"),
+            "urn:isrc:USQX91300109".to_string(),
+            "urn:isrc:USQX91300108".to_string(),
+        ]);
+
+        // Platform MBIDs are kept even when the lookup fails
+        assert_eq!(identifiers(source(&[], Some(GET_LUCKY))), vec![
+            format!("https://musicbrainz.org/recording/{GET_LUCKY}"),
+            format!("https://musicbrainz.org/artist/{DAFT_PUNK}"),
+        ]);
+        assert_eq!(identifiers(source(&["GBDUW0000059"], Some(GET_LUCKY_EDIT))), vec![
+            format!("https://musicbrainz.org/recording/{GET_LUCKY_EDIT}"),
+            "urn:isrc:GBDUW0000059".to_string(),
+        ]);
+        assert!(identifiers(source(&[], None)).is_empty());
+
+        let track = to_track(source(&[], None), &mb_client);
+        assert_eq!((track.title.as_str(), track.creator.as_str()), ("Get Lucky", "Daft Punk"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+// Playlist formats other than XSPF and JSPF, read into the shared Playlist
+// model. Parse errors name the line they were found on.
+
+pub mod csv;
+pub mod m3u;
+pub mod pls;
+
+/// Split `Artist - Title` as used in M3U and PLS entry titles
+pub fn split_artist_title(text: &str) -> Option<(String, String)> {
+    let (artist, title) = text.split_once(" - ")?;
+    let (artist, title) = (artist.trim(), title.trim());
+
+    if artist.is_empty() || title.is_empty() {
+        None
+    } else {
+        Some((artist.to_string(), title.to_string()))
+    }
+}
+
+/// `Artist - Title` from a file name like `Artist - Title.mp3`, used when an
+/// entry has no title of its own
+pub fn artist_title_from_path(path: &str) -> Option<(String, String)> {
+    let name = path.rsplit(['/', '\\']).next()?;
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    split_artist_title(&urlencoding::decode(stem).ok()?)
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::mbzlists::{Playlist, Track, Tracklist};
+
+// Header names for each column, as written by Exportify and similar tools
+const TITLE_COLUMNS: &[&str] = &["track name", "title", "name", "track"];
+const ARTIST_COLUMNS: &[&str] = &["artist name(s)", "artist name", "artists", "artist"];
+const ALBUM_COLUMNS: &[&str] = &["album name", "album"];
+const DURATION_COLUMNS: &[&str] = &["track duration (ms)", "duration (ms)", "duration_ms"];
+const ISRC_COLUMNS: &[&str] = &["isrc"];
+const URI_COLUMNS: &[&str] = &["track uri", "uri", "spotify uri"];
+
+/// Parse a CSV playlist export with a header row. Title and artist columns are
+/// required; album, duration, ISRC and track URI columns are used when present.
+pub fn parse(text: &str, default_title: &str) -> Result<Playlist> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(false)
+        .from_reader(text.trim_start_matches('\u{feff}').as_bytes());
+
+    let headers = reader.headers().context("Failed to read the CSV header")?.clone();
+    let column = |names: &[&str]| {
+        headers.iter().position(|header| names.contains(&header.trim().to_lowercase().as_str()))
+    };
+
+    let title_col = column(TITLE_COLUMNS).ok_or_else(|| anyhow!("No track name column in the CSV header"))?;
+    let artist_col = column(ARTIST_COLUMNS).ok_or_else(|| anyhow!("No artist column in the CSV header"))?;
+    let album_col = column(ALBUM_COLUMNS);
+    let duration_col = column(DURATION_COLUMNS);
+    let isrc_col = column(ISRC_COLUMNS);
+    let uri_col = column(URI_COLUMNS);
+
+    let mut tracks = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let line_num = record.position().map_or(0, |pos| pos.line());
+        let field = |col: Option<usize>| {
+            col.and_then(|col| record.get(col))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+
+        let title = field(Some(title_col)).ok_or_else(|| anyhow!("Line {line_num}: missing track name"))?;
+        let artist = field(Some(artist_col)).ok_or_else(|| anyhow!("Line {line_num}: missing artist"))?;
+
+        let duration = match field(duration_col) {
+            Some(duration) => Some(duration.parse::<u64>()
+                .map_err(|_| anyhow!("Line {line_num}: invalid duration {duration}"))?),
+            None => None,
+        };
+
+        tracks.push(Track {
+            locations: field(uri_col).map(|uri| vec![uri.to_string()]).unwrap_or_default(),
+            identifiers: field(isrc_col).map(|isrc| vec![format!("urn:isrc:{isrc}")]).unwrap_or_default(),
+            title: title.to_string(),
+            creator: artist.to_string(),
+            album: field(album_col).map(|album| album.to_string()),
+            duration,
+            ..Default::default()
+        });
+    }
+
+    Ok(Playlist {
+        title: default_title.to_string(),
+        tracklist: Tracklist { tracks },
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header as written by Exportify
+    const EXPORTIFY_HEADER: &str = "\u{feff}\"Track URI\",\"Track Name\",\"Artist URI(s)\",\"Artist Name(s)\",\"Album URI\",\"Album Name\",\
+        \"Album Artist URI(s)\",\"Album Artist Name(s)\",\"Album Release Date\",\"Album Image URL\",\"Disc Number\",\"Track Number\",\
+        \"Track Duration (ms)\",\"Track Preview URL\",\"Explicit\",\"Popularity\",\"ISRC\",\"Added By\",\"Added At\"";
+
+    #[test]
+    fn parses_exportify() {
+        let csv = format!("{EXPORTIFY_HEADER}\n\
+            \"spotify:track:0DiWol3AO6WpXZgp0goxAV\",\"One More Time\",\"spotify:artist:4tZwfgrHOc3mvqYlEYSvVi\",\"Daft Punk\",\"spotify:album:2noRn2Aes5aoNVsU6iWThc\",\"Discovery\",\
+            \"spotify:artist:4tZwfgrHOc3mvqYlEYSvVi\",\"Daft Punk\",\"2001-03-12\",\"\",\"1\",\"1\",\"320357\",\"\",\"false\",\"75\",\"GBDUW0000053\",\"\",\"\"\n");
+
+        let playlist = parse(&csv, "Default").unwrap();
+        let track = &playlist.tracklist.tracks[0];
+        assert_eq!((track.creator.as_str(), track.title.as_str()), ("Daft Punk", "One More Time"));
+        assert_eq!(track.album.as_deref(), Some("Discovery"));
+        assert_eq!(track.duration, Some(320357));
+        assert_eq!(track.identifiers, vec!["urn:isrc:GBDUW0000053"]);
+        assert_eq!(track.locations, vec!["spotify:track:0DiWol3AO6WpXZgp0goxAV"]);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = parse("Track Name,Artist Name(s)\nA,B\nC,\n", "Default").unwrap_err();
+        assert_eq!(err.to_string(), "Line 3: missing artist");
+
+        let err = parse("Track Name,Artist Name(s),Duration (ms)\nA,B,long\n", "Default").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: invalid duration long");
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use url::Url;
+
+use crate::formats::{artist_title_from_path, split_artist_title};
+use crate::mbzlists::{Playlist, Track, Tracklist};
+
+/// Parse an M3U or M3U8 playlist. Track details come from `#EXTINF:duration,Artist - Title`
+/// lines, or from the file name when there is none or its title has no
+/// artist. Entries without an artist and title are skipped with a warning.
+pub fn parse(text: &str, default_title: &str) -> Result<Playlist> {
+    let mut title = None;
+    let mut tracks = vec![];
+    let mut extinf: Option<(usize, Option<u64>, &str)> = None;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_num = idx + 1;
+        let line = line.trim_start_matches('\u{feff}').trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            if let Some((prev_line, ..)) = extinf {
+                return Err(anyhow!("Line {line_num}: #EXTINF without an entry after the one on line {prev_line}"));
+            }
+            let (duration, display) = parse_extinf(info).map_err(|err| anyhow!("Line {line_num}: {err}"))?;
+            extinf = Some((line_num, duration, display));
+        } else if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            title = Some(name.trim().to_string());
+        } else if line.starts_with('#') {
+            // #EXTM3U and other directives
+            continue;
+        } else {
+            let (duration, artist_title) = match extinf.take() {
+                Some((extinf_line, duration, display)) => {
+                    let artist_title = split_artist_title(display);
+                    if artist_title.is_none() {
+                        warn!("Line {extinf_line}: #EXTINF title is not in the form Artist - Title, using the file name: {display}");
+                    }
+                    (duration, artist_title)
+                },
+                None => (None, None),
+            };
+            let track = artist_title.or_else(|| artist_title_from_path(line))
+                .map(|(artist, title)| Track { title, creator: artist, duration, ..Default::default() });
+
+            match track {
+                Some(mut track) => {
+                    track.locations.push(line.to_string());
+                    tracks.push(track);
+                },
+                None => warn!("Line {line_num}: skipping {line} without artist and title"),
+            }
+        }
+    }
+
+    if let Some((line_num, ..)) = extinf {
+        return Err(anyhow!("Line {line_num}: #EXTINF without an entry"));
+    }
+
+    Ok(Playlist {
+        title: title.unwrap_or(default_title.to_string()),
+        tracklist: Tracklist { tracks },
+        ..Default::default()
+    })
+}
+
+/// Write the tracks with a location as M3U8, using the first location of
+/// each. File URLs are written as plain paths.
+pub fn write(playlist: &Playlist) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    m3u.push_str(&format!("#PLAYLIST:{}\n", playlist.title));
+
+    for track in &playlist.tracklist.tracks {
+        let Some(location) = track.locations.first() else {
+            continue;
+        };
+
+        let duration = track.duration.map(|ms| (ms as f64 / 1000.0).round() as i64).unwrap_or(-1);
+        m3u.push_str(&format!("#EXTINF:{duration},{} - {}\n", track.creator, track.title));
+
+        let path = Url::parse(location).ok()
+            .filter(|url| url.scheme() == "file")
+            .and_then(|url| url.to_file_path().ok());
+        match path {
+            Some(path) => m3u.push_str(&format!("{}\n", path.display())),
+            None => m3u.push_str(&format!("{location}\n")),
+        }
+    }
+    m3u
+}
+
+// `duration[ key="value" ...],Artist - Title` with the duration in seconds and
+// -1 for unknown, giving the duration and the display title
+fn parse_extinf(info: &str) -> Result<(Option<u64>, &str)> {
+    let (head, display) = info.split_once(',')
+        .ok_or_else(|| anyhow!("#EXTINF is missing the comma before the title"))?;
+
+    let duration = head.split_whitespace().next().unwrap_or_default();
+    let duration = duration.parse::<f64>()
+        .map_err(|_| anyhow!("Invalid #EXTINF duration: {duration}"))?;
+    let duration = if duration < 0.0 { None } else { Some((duration * 1000.0).round() as u64) };
+
+    Ok((duration, display))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_extended_m3u() {
+        let m3u = "\u{feff}#EXTM3U\n#PLAYLIST:Mix\n\
+            #EXTINF:-1,Daft Punk - One More Time\nhttp://example.com/stream\n\
+            #EXTINF:123,Some Title\n/music/Cover Band - Get Lucky.flac\n\
+            #EXTINF:200 tvg-id=\"x\",No Artist\n/music/no artist.flac\n\
+            /music/Artist%20Name - Plain.mp3\n";
+
+        let playlist = parse(m3u, "Default").unwrap();
+        assert_eq!(playlist.title, "Mix");
+
+        let tracks = &playlist.tracklist.tracks;
+        assert_eq!(tracks.len(), 3);
+        assert_eq!((tracks[0].creator.as_str(), tracks[0].title.as_str(), tracks[0].duration), ("Daft Punk", "One More Time", None));
+        assert_eq!((tracks[1].creator.as_str(), tracks[1].title.as_str(), tracks[1].duration), ("Cover Band", "Get Lucky", Some(123000)));
+        assert_eq!((tracks[2].creator.as_str(), tracks[2].title.as_str()), ("Artist Name", "Plain"));
+        assert_eq!(tracks[2].locations, vec!["/music/Artist%20Name - Plain.mp3"]);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = parse("#EXTM3U\n\n#EXTINF:abc,A - B\na.mp3\n", "Default").unwrap_err();
+        assert_eq!(err.to_string(), "Line 3: Invalid #EXTINF duration: abc");
+
+        let err = parse("#EXTINF:1,A - B\n#EXTINF:2,C - D\nc.mp3\n", "Default").unwrap_err();
+        assert!(err.to_string().starts_with("Line 2:"), "{err}");
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use log::warn;
+
+use crate::formats::{artist_title_from_path, split_artist_title};
+use crate::mbzlists::{Playlist, Track, Tracklist};
+
+#[derive(Default)]
+struct Entry {
+    file: Option<String>,
+    title: Option<String>,
+    length: Option<u64>,
+}
+
+/// Parse a PLS playlist. `TitleN` entries are read as `Artist - Title`, falling
+/// back to the file name. Entries without an artist and title are skipped
+/// with a warning.
+pub fn parse(text: &str, default_title: &str) -> Result<Playlist> {
+    let mut in_playlist = false;
+    let mut entries: BTreeMap<u32, Entry> = BTreeMap::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_num = idx + 1;
+        let line = line.trim_start_matches('\u{feff}').trim();
+
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_playlist = line.eq_ignore_ascii_case("[playlist]");
+            continue;
+        }
+
+        if !in_playlist {
+            return Err(anyhow!("Line {line_num}: entry outside the [playlist] section"));
+        }
+
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| anyhow!("Line {line_num}: expected key=value, found {line}"))?;
+        let (key, value) = (key.trim().to_lowercase(), value.trim().to_string());
+
+        if key == "numberofentries" || key == "version" {
+            continue;
+        }
+
+        let (field, index) = ["file", "title", "length"].into_iter()
+            .find_map(|field| key.strip_prefix(field).map(|index| (field, index)))
+            .ok_or_else(|| anyhow!("Line {line_num}: unknown key {key}"))?;
+        let index = index.parse::<u32>()
+            .map_err(|_| anyhow!("Line {line_num}: invalid entry number in {key}"))?;
+
+        let entry = entries.entry(index).or_default();
+        match field {
+            "file" => entry.file = Some(value),
+            "title" => entry.title = Some(value),
+            _ => {
+                let length = value.parse::<i64>()
+                    .map_err(|_| anyhow!("Line {line_num}: invalid length {value}"))?;
+                entry.length = u64::try_from(length).ok().map(|secs| secs * 1000);
+            },
+        }
+    }
+
+    if !in_playlist && entries.is_empty() {
+        return Err(anyhow!("Missing [playlist] section"));
+    }
+
+    let mut tracks = vec![];
+    for (index, entry) in entries {
+        let Some(file) = entry.file else {
+            return Err(anyhow!("Entry {index} has no File{index}"));
+        };
+
+        let artist_title = entry.title.as_deref()
+            .and_then(split_artist_title)
+            .or_else(|| artist_title_from_path(&file));
+
+        match artist_title {
+            Some((artist, title)) => tracks.push(Track {
+                locations: vec![file],
+                title,
+                creator: artist,
+                duration: entry.length,
+                ..Default::default()
+            }),
+            None => warn!("Skipping entry {index} ({file}) without artist and title"),
+        }
+    }
+
+    Ok(Playlist {
+        title: default_title.to_string(),
+        tracklist: Tracklist { tracks },
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pls() {
+        let pls = "\u{feff}[playlist]\nNumberOfEntries=3\n\
+            File1=http://example.com/radio\nTitle1=Some Station - Live\nLength1=-1\n\
+            File2=/music/Daft Punk - One More Time.mp3\nLength2=320\n\
+            File3=/music/untitled.mp3\nVersion=2\n";
+
+        let playlist = parse(pls, "Default").unwrap();
+        assert_eq!(playlist.title, "Default");
+
+        let tracks = &playlist.tracklist.tracks;
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].creator.as_str(), tracks[0].title.as_str(), tracks[0].duration), ("Some Station", "Live", None));
+        assert_eq!((tracks[1].creator.as_str(), tracks[1].title.as_str(), tracks[1].duration), ("Daft Punk", "One More Time", Some(320000)));
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = parse("[playlist]\nFile1=a.mp3\nnot an entry\n", "Default").unwrap_err();
+        assert_eq!(err.to_string(), "Line 3: expected key=value, found not an entry");
+
+        let err = parse("[playlist]\nFile1=a.mp3\nLength1=long\n", "Default").unwrap_err();
+        assert_eq!(err.to_string(), "Line 3: invalid length long");
+    }
+}
@@ -0,0 +1,340 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+use crate::cache::ResolutionCache;
+use crate::formats;
+use crate::interactive;
+use crate::matching::{self, ArtistNames};
+use crate::mbzlists::{Playlist, Track, Tracklist};
+use crate::musicbrainz::MusicBrainzClient;
+use crate::overrides::{Override, Overrides};
+use crate::source::ListSource;
+
+/// A track found on a target platform
+#[derive(Debug, Clone, Default)]
+pub struct Candidate {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<u64>,
+    pub year: Option<u32>,
+    /// MusicBrainz recording id from the platform metadata. Only taken from
+    /// fields that hold recording ids, never release track ids.
+    pub recording_mbid: Option<String>,
+}
+
+impl Candidate {
+    /// Matching recording MBIDs settle the match, otherwise title and artist
+    /// have to match. Differing MBIDs only rule the candidate out when its id
+    /// is a well formed MBID, tags sometimes hold several ids or other values.
+    pub fn matches(&self, track: &Track, artist_names: &ArtistNames) -> bool {
+        if let (Some(mbid), Some(track_mbid)) = (&self.recording_mbid, track.recording_mbid()) {
+            if mbid.eq_ignore_ascii_case(&track_mbid) {
+                return true;
+            }
+            if is_mbid(mbid) {
+                return false;
+            }
+        }
+
+        matching::title_matches(&self.title, &track.title) && artist_names.matches(&self.artist)
+    }
+}
+
+// MBIDs are UUIDs like 8f3471b5-7e6a-48da-86a9-c1c07a0f47ae
+fn is_mbid(id: &str) -> bool {
+    id.len() == 36 && id.char_indices().all(|(i, c)| match i {
+        8 | 13 | 18 | 23 => c == '-',
+        _ => c.is_ascii_hexdigit(),
+    })
+}
+
+impl std::fmt::Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.artist, self.title)?;
+
+        let mut details = vec![];
+        if let Some(album) = &self.album {
+            details.push(album.clone());
+        }
+        if let Some(duration) = self.duration {
+            details.push(format!("{}:{:02}", duration / 60, duration % 60));
+        }
+        if let Some(year) = self.year {
+            details.push(year.to_string());
+        }
+
+        if details.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " [{}]", details.join(", "))
+        }
+    }
+}
+
+/// A platform that the command line tool can import playlists into
+pub trait Target {
+    /// Platform name used in override files
+    fn override_platform(&self) -> &'static str;
+
+    /// Platform name used in the resolution cache. Targets with server
+    /// specific ids should include the server in here.
+    fn cache_platform(&self) -> String;
+
+    /// Free text search, also used for custom queries in interactive review
+    fn search(&self, query: &str) -> Result<Vec<Candidate>>;
+
+    /// Targets that work without network access skip MusicBrainz lookups
+    fn offline(&self) -> bool {
+        false
+    }
+
+    /// Candidates for a playlist track
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        self.search(&format!("{} {}", track.title, track.creator))
+    }
+
+    /// Whether only the best ranked candidate may be picked without
+    /// interactive review
+    fn top_result_only(&self) -> bool {
+        false
+    }
+
+    /// URI (stream URL, file URL or similar) a resolved track can be played
+    /// from, used when writing resolved playlists. Never contains credentials.
+    fn location(&self, _id: &str) -> Option<String> {
+        None
+    }
+
+    /// Like [`Target::location`], with the credentials servers need to stream
+    /// the track. Only used with `--embed-credentials`.
+    fn location_with_credentials(&self, id: &str) -> Option<String> {
+        self.location(id)
+    }
+
+    /// Create (or update) the playlist `name` with the given track ids
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()>;
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// Playlist file (XSPF, JSPF, M3U/M3U8, PLS or CSV), or a view id with `--source`
+    pub xspf: std::path::PathBuf,
+    pub name: Option<String>,
+
+    #[arg(long)]
+    pub no_create: bool,
+
+    /// Review unresolved and ambiguous tracks on the terminal
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Save choices made in interactive review as overrides
+    #[arg(long, requires = "interactive")]
+    pub save_overrides: bool,
+
+    /// Also write the playlist as XSPF with locations of resolved tracks
+    #[arg(long)]
+    pub write_xspf: Option<std::path::PathBuf>,
+
+    /// Also write the resolved tracks as an M3U8 playlist of their locations
+    #[arg(long)]
+    pub write_m3u8: Option<std::path::PathBuf>,
+
+    /// Put the server credentials into the stream URLs of written playlists,
+    /// so players can use them as they are
+    #[arg(long)]
+    pub embed_credentials: bool,
+
+    /// Load the list by view id from an mbzlists source: a directory of
+    /// XSPF/JSPF files, an exported archive or a server host
+    #[arg(long)]
+    pub source: Option<String>,
+}
+
+/// Resolve the playlist in `args` on `target` and create it there. Tracks are
+/// resolved from overrides first, then the resolution cache (unless
+/// `no_cache`) and then by searching the target.
+pub fn run(target: &impl Target, args: ImportArgs, no_cache: bool) -> Result<()> {
+    let pl = match &args.source {
+        Some(source) => ListSource::parse(source)?.load(&args.xspf.to_string_lossy())?,
+        None => Playlist::from_file(args.xspf)?,
+    };
+    let pl_name = args.name.unwrap_or(pl.title.clone());
+
+    info!("Read total {} tracks in the file", pl.tracklist.tracks.len());
+
+    let mb_client = if target.offline() { None } else { Some(MusicBrainzClient::from_env()?) };
+    let cache = if no_cache { None } else { Some(ResolutionCache::open_default()?) };
+    let cache_platform = target.cache_platform();
+    let mut overrides = Overrides::load_default()?;
+
+    let mut resolved_ids = vec![];
+    for (idx, track) in pl.tracklist.tracks.iter().enumerate() {
+        if let Some(id) = overrides.lookup(target.override_platform(), track) {
+            resolved_ids.push((idx, id));
+            continue;
+        }
+
+        if let Some(cache) = &cache && let Some(entry) = cache.get(&cache_platform, track)? {
+            match entry.platform_id {
+                Some(id) => {
+                    resolved_ids.push((idx, id));
+                    continue;
+                },
+                // Cached misses are worth another look in interactive mode
+                None if !args.interactive => {
+                    report_unresolved(track, true);
+                    continue;
+                },
+                None => {},
+            }
+        }
+
+        let candidates = match target.candidates(track) {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                // Not cached since this might be a temporary failure
+                warn!("Search failed for {} - {}: {err}", track.creator, track.title);
+                continue;
+            },
+        };
+
+        let artist_names = ArtistNames::for_track(track, mb_client.as_ref());
+        let n_matches = candidates.iter().filter(|c| c.matches(track, &artist_names)).count();
+
+        let resolved = if args.interactive && n_matches != 1 {
+            let choice = interactive::review(track, candidates, |query| target.search(query))?;
+
+            if let Some(candidate) = &choice && args.save_overrides {
+                let path = Overrides::default_path().ok_or(anyhow!("Unable to find a config directory, set MBZR_OVERRIDES"))?;
+                overrides.add(Override::for_track(track, target.override_platform(), &candidate.id))?;
+                overrides.save(&path)?;
+            }
+            choice
+        } else {
+            // Final check to ensure search quality
+            let n_considered = if target.top_result_only() { 1 } else { candidates.len() };
+            candidates.into_iter().take(n_considered).find(|c| c.matches(track, &artist_names))
+        };
+
+        if let Some(cache) = &cache {
+            cache.put(&cache_platform, track, resolved.as_ref().map(|c| c.id.as_str()))?;
+        }
+
+        match resolved {
+            Some(candidate) => resolved_ids.push((idx, candidate.id)),
+            None => report_unresolved(track, false),
+        }
+    }
+
+    info!("Resolved total {} tracks", resolved_ids.len());
+
+    if args.write_xspf.is_some() || args.write_m3u8.is_some() {
+        if args.embed_credentials {
+            warn!("Written playlists contain credentials for {}, don't share them", target.override_platform());
+        }
+        let location = |id: &str| if args.embed_credentials { target.location_with_credentials(id) } else { target.location(id) };
+        let resolved_pl = with_locations(&pl, &pl_name, &resolved_ids, location);
+
+        if let Some(path) = &args.write_xspf {
+            std::fs::write(path, resolved_pl.to_xspf()).with_context(|| format!("Unable to write {:?}", path))?;
+            info!("Wrote {:?}", path);
+        }
+
+        if let Some(path) = &args.write_m3u8 {
+            let tracks = resolved_ids.iter()
+                .filter(|(_, id)| location(id).is_some())
+                .map(|(idx, _)| resolved_pl.tracklist.tracks[*idx].clone())
+                .collect::<Vec<Track>>();
+            if tracks.len() < resolved_ids.len() {
+                warn!("{} resolved tracks have no location on {}", resolved_ids.len() - tracks.len(), target.override_platform());
+            }
+
+            let m3u_pl = Playlist { title: pl_name.clone(), tracklist: Tracklist { tracks }, ..Default::default() };
+            std::fs::write(path, formats::m3u::write(&m3u_pl)).with_context(|| format!("Unable to write {:?}", path))?;
+            info!("Wrote {:?}", path);
+        }
+    }
+
+    let ids = resolved_ids.into_iter().map(|(_, id)| id).collect::<Vec<String>>();
+    if !ids.is_empty() && !args.no_create {
+        target.create_playlist(&pl_name, ids)?;
+        info!("Created playlist: {pl_name}");
+    }
+    Ok(())
+}
+
+// Copy of the playlist with target locations put first on resolved tracks
+fn with_locations(pl: &Playlist, name: &str, resolved_ids: &[(usize, String)], location: impl Fn(&str) -> Option<String>) -> Playlist {
+    let mut resolved_pl = pl.clone();
+    resolved_pl.title = name.to_string();
+
+    for (idx, id) in resolved_ids {
+        if let Some(location) = location(id) {
+            let track = &mut resolved_pl.tracklist.tracks[*idx];
+            track.locations.retain(|l| *l != location);
+            track.locations.insert(0, location);
+        }
+    }
+    resolved_pl
+}
+
+// Log an unresolved track along with the command to fix it manually
+fn report_unresolved(track: &Track, cached: bool) {
+    let selector = match track.recording_mbid() {
+        Some(mbid) => format!("--mbid {mbid}"),
+        None => format!("--title {:?} --artist {:?}", track.title, track.creator),
+    };
+    let cached = if cached { " (cached)" } else { "" };
+
+    info!("Unable to resolve {} - {}{cached}. To set manually: override add <platform> <id> {selector}", track.creator, track.title);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MBID: &str = "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae";
+    const OTHER_MBID: &str = "0383dadf-2a4e-4d10-a46a-e9e041da8eb3";
+
+    fn candidate(title: &str, artist: &str, mbid: Option<&str>) -> Candidate {
+        Candidate { title: title.to_string(), artist: artist.to_string(), recording_mbid: mbid.map(str::to_string), ..Default::default() }
+    }
+
+    #[test]
+    fn matches_candidates() {
+        let track = Track {
+            title: "Get Lucky".to_string(),
+            creator: "Daft Punk".to_string(),
+            identifiers: vec![format!("https://musicbrainz.org/recording/{MBID}")],
+            ..Default::default()
+        };
+        let artist_names = ArtistNames::from_track(&track);
+
+        assert!(candidate("Get Lucky", "Daft Punk", None).matches(&track, &artist_names));
+        assert!(!candidate("Lose Yourself to Dance", "Daft Punk", None).matches(&track, &artist_names));
+
+        // MBIDs settle it either way
+        assert!(candidate("Get Lucky (Radio Edit)", "Daft Punk", Some(MBID)).matches(&track, &artist_names));
+        assert!(candidate("Get Lucky", "Daft Punk", Some(&MBID.to_uppercase())).matches(&track, &artist_names));
+        assert!(!candidate("Get Lucky", "Daft Punk", Some(OTHER_MBID)).matches(&track, &artist_names));
+
+        // Unless the candidate's id isn't one
+        let ids = format!("{OTHER_MBID}/{MBID}");
+        assert!(candidate("Get Lucky", "Daft Punk", Some(&ids)).matches(&track, &artist_names));
+        assert!(!candidate("Something Else", "Daft Punk", Some(&ids)).matches(&track, &artist_names));
+    }
+
+    #[test]
+    fn checks_mbids() {
+        assert!(is_mbid(MBID));
+        assert!(is_mbid(&MBID.to_uppercase()));
+        assert!(!is_mbid(""));
+        assert!(!is_mbid(&MBID.replace('-', "")));
+        assert!(!is_mbid(&MBID.replace('a', "g")));
+        assert!(!is_mbid(&format!("{MBID};{OTHER_MBID}")));
+    }
+}
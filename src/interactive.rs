@@ -0,0 +1,88 @@
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+use crate::mbzlists::Track;
+
+/// Ask the user to pick a candidate for `track` on the terminal. Entering a
+/// number picks that candidate, `s` (or an empty line) skips the track and any
+/// other text is used as a new search query with `search`.
+pub fn review<C: std::fmt::Display>(
+    track: &Track,
+    mut candidates: Vec<C>,
+    search: impl Fn(&str) -> Result<Vec<C>>,
+) -> Result<Option<C>> {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+
+    loop {
+        writeln!(stdout, "\n{} - {}", track.creator, track.title)?;
+        if candidates.is_empty() {
+            writeln!(stdout, "  No candidates found")?;
+        }
+        for (i, candidate) in candidates.iter().enumerate() {
+            writeln!(stdout, "  {}. {}", i + 1, candidate)?;
+        }
+        write!(stdout, "Pick a number, [s]kip, or type a new search query: ")?;
+        stdout.flush()?;
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input)? == 0 {
+            // EOF, nothing more to ask
+            return Ok(None);
+        }
+
+        match parse_choice(&input, candidates.len()) {
+            Choice::Pick(idx) => return Ok(Some(candidates.swap_remove(idx))),
+            Choice::Skip => return Ok(None),
+            Choice::OutOfRange => writeln!(stdout, "No candidate numbered {}", input.trim())?,
+            // A failed search keeps the current candidates so that nothing
+            // resolved so far is lost
+            Choice::Query(query) => match search(query) {
+                Ok(results) => candidates = results,
+                Err(err) => writeln!(stdout, "Search failed: {err}")?,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Choice<'a> {
+    /// Index of the picked candidate
+    Pick(usize),
+    Skip,
+    OutOfRange,
+    Query(&'a str),
+}
+
+fn parse_choice(input: &str, n_candidates: usize) -> Choice<'_> {
+    let input = input.trim();
+    if input.is_empty() || input == "s" {
+        return Choice::Skip;
+    }
+
+    match input.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= n_candidates => Choice::Pick(n - 1),
+        Ok(_) => Choice::OutOfRange,
+        Err(_) => Choice::Query(input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_choices() {
+        assert_eq!(parse_choice("1\n", 3), Choice::Pick(0));
+        assert_eq!(parse_choice(" 3 ", 3), Choice::Pick(2));
+        assert_eq!(parse_choice("0", 3), Choice::OutOfRange);
+        assert_eq!(parse_choice("4", 3), Choice::OutOfRange);
+        assert_eq!(parse_choice("1", 0), Choice::OutOfRange);
+        assert_eq!(parse_choice("s\n", 3), Choice::Skip);
+        assert_eq!(parse_choice("\n", 3), Choice::Skip);
+        assert_eq!(parse_choice("  daft punk  get lucky\n", 3), Choice::Query("daft punk  get lucky"));
+        assert_eq!(parse_choice("S", 3), Choice::Query("S"));
+        assert_eq!(parse_choice("-1", 3), Choice::Query("-1"));
+        assert_eq!(parse_choice("1999", 3), Choice::OutOfRange);
+    }
+}
@@ -1,66 +1,408 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use import::ImportArgs;
 use log::info;
+use musicbrainz::MusicBrainzClient;
+use mbzlists::Playlist;
+use overrides::{Override, Overrides};
+use cache::ResolutionCache;
+use platform::ampache::AmpacheClient;
+use platform::beets::{BeetsLibrary, BeetsOutput};
+use platform::emby::EmbyClient;
+use platform::funkwhale::FunkwhaleClient;
+use platform::jellyfin::JellyfinClient;
+use platform::kodi::{KodiClient, KodiPlaylistFormat};
+use platform::listenbrainz::{Jspf, ListenBrainzClient};
+use platform::local::{LocalLibrary, M3u8Output};
+use platform::mpd::MpdClient;
+use platform::plex::PlexClient;
 use platform::subsonic::SubsonicClient;
-use anyhow::Result;
+use source::ListSource;
+use anyhow::{anyhow, Result};
 
+mod cache;
+mod export;
+mod formats;
+mod import;
+mod interactive;
 mod platform;
+mod source;
 mod webapp;
+mod matching;
 mod mbzlists;
+mod musicbrainz;
+mod overrides;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
     platform: Platforms,
+
+    /// Skip the resolution cache, searching every track again
+    #[arg(long, global = true)]
+    no_cache: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Platforms {
     Subsonic {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    Jellyfin {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    Plex {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    Emby {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    Ampache {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    Funkwhale {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    /// Match against a local music directory and write an M3U8 playlist
+    Local {
+        #[command(flatten)]
+        import: ImportArgs,
+
+        /// Directory with the tagged music files
+        #[arg(long)]
+        library: std::path::PathBuf,
+
+        /// Playlist file to write, defaults to <name>.m3u8
+        #[arg(long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Write absolute paths instead of paths relative to the playlist
+        #[arg(long)]
+        absolute: bool,
+    },
+    /// Match against a beets library and write an M3U8 playlist or save it
+    /// on MPD
+    Beets {
+        #[command(flatten)]
+        import: ImportArgs,
+
+        /// Path to the beets library.db, defaults to the one beets uses
+        #[arg(long)]
+        library: Option<std::path::PathBuf>,
+
+        /// Playlist file to write, defaults to <name>.m3u8
+        #[arg(long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Write absolute paths instead of paths relative to the playlist
+        #[arg(long)]
+        absolute: bool,
+
+        /// Save the playlist on MPD instead, given its music directory
+        #[arg(long, value_name = "MUSIC_DIR", conflicts_with_all = ["output", "absolute"])]
+        mpd: Option<std::path::PathBuf>,
+    },
+    /// Match against the Kodi music library and write the playlist into the
+    /// Kodi profile
+    Kodi {
+        #[command(flatten)]
+        import: ImportArgs,
+
+        /// Kodi profile directory, defaults to `KODI_PROFILE` or ~/.kodi/userdata
+        #[arg(long)]
+        profile: Option<std::path::PathBuf>,
+
+        #[arg(long, value_enum, default_value = "m3u")]
+        format: KodiPlaylistFormat,
+    },
+    /// Save the playlist on an MPD (or Mopidy) server
+    Mpd {
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    /// Create a playlist on ListenBrainz (needs `LB_TOKEN`) or write it as JSPF
+    Listenbrainz {
+        /// Playlist file (XSPF, JSPF, M3U/M3U8, PLS or CSV)
         xspf: std::path::PathBuf,
         name: Option<String>,
 
+        /// Only write the JSPF to this file, without contacting ListenBrainz
+        #[arg(long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Make the playlist public on ListenBrainz
         #[arg(long)]
-        no_create: bool,
+        public: bool,
+    },
+    /// Read a playlist from a platform back into an XSPF file for mbzlists
+    Export {
+        platform: ExportPlatform,
+
+        /// Playlist url or id (or name for Subsonic)
+        playlist: String,
+
+        /// File to write, defaults to stdout
+        output: Option<std::path::PathBuf>,
+    },
+    /// List the view ids in an mbzlists source, or print one list as XSPF
+    Lists {
+        /// Directory of XSPF/JSPF files, exported archive or server host
+        source: String,
+        view_id: Option<String>,
+
+        /// File to write the list to, defaults to stdout
+        #[arg(long, requires = "view_id")]
+        output: Option<std::path::PathBuf>,
     },
     Webapp,
+    /// Look up an entity on MusicBrainz (respecting `MB_HOST`) and print it as JSON
+    Musicbrainz {
+        entity: MbEntity,
+        mbid: String,
+    },
+    /// Inspect, export or purge the resolution cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Manage manual track to platform id mappings (see `MBZR_OVERRIDES`)
+    Override {
+        #[command(subcommand)]
+        action: OverrideAction,
+    },
 }
 
-#[actix_web::main]
-async fn main() -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum OverrideAction {
+    /// Map a track to an id on a platform (subsonic, jellyfin, plex, emby, ampache, funkwhale, local, beets, kodi, mpd, spotify, youtube)
+    Add {
+        platform: String,
+        id: String,
+
+        /// Recording MBID of the track
+        #[arg(long)]
+        mbid: Option<String>,
+
+        #[arg(long)]
+        title: Option<String>,
+
+        #[arg(long)]
+        artist: Option<String>,
+    },
+    /// Print all overrides
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// List cached resolutions
+    List {
+        #[arg(long)]
+        platform: Option<String>,
+    },
+    /// Write all cached resolutions as JSON to a file or stdout
+    Export {
+        output: Option<std::path::PathBuf>,
+    },
+    /// Delete cached resolutions
+    Purge {
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Only delete entries past their TTL
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum ExportPlatform {
+    Spotify,
+    Youtube,
+    Subsonic,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum MbEntity {
+    Recording,
+    Artist,
+    Release,
+}
+
+fn main() -> Result<()> {
     let args = Args::parse();
     env_logger::init();
 
-    match args.platform {
-        Platforms::Subsonic { xspf, name, no_create } => {
-            let pl = mbzlists::Playlist::from_xspf(xspf)?;
-            let pl_name = name.unwrap_or(pl.title.clone());
-
-            info!("Read total {} tracks in the file", pl.tracklist.tracks.len());
-
-            let ss_client = SubsonicClient::new(
-                format!("{}/rest", std::env::var("SS_HOST").expect("SS_HOST not set")),
-                std::env::var("SS_USER").expect("SS_USER not set"),
-                urlencoding::encode(&std::env::var("SS_PASS").expect("SS_PASS not set")).to_string(),
-            );
-
-            let mut ss_tracks = vec![];
-            for track in &pl.tracklist.tracks {
-                match ss_client.resolve(track) {
-                    Some(ss_track) => ss_tracks.push(ss_track),
-                    None => info!("Unable to resolve {:?}", track)
-                }
-            }
+    let subsonic_client = || SubsonicClient::new(
+        format!("{}/rest", std::env::var("SS_HOST").expect("SS_HOST not set")),
+        std::env::var("SS_USER").expect("SS_USER not set"),
+        std::env::var("SS_PASS").expect("SS_PASS not set"),
+    );
 
-            info!("Resolved total {} tracks", ss_tracks.len());
+    match args.platform {
+        Platforms::Subsonic { import } => {
+            import::run(&subsonic_client(), import, args.no_cache)
+        },
+        Platforms::Jellyfin { import } => {
+            let jf_client = JellyfinClient::from_env()?;
+            import::run(&jf_client, import, args.no_cache)
+        },
+        Platforms::Plex { import } => {
+            let plex_client = PlexClient::from_env()?;
+            import::run(&plex_client, import, args.no_cache)
+        },
+        Platforms::Emby { import } => {
+            let emby_client = EmbyClient::from_env()?;
+            import::run(&emby_client, import, args.no_cache)
+        },
+        Platforms::Ampache { import } => {
+            let ampache_client = AmpacheClient::from_env()?;
+            import::run(&ampache_client, import, args.no_cache)
+        },
+        Platforms::Funkwhale { import } => {
+            let fw_client = FunkwhaleClient::from_env()?;
+            import::run(&fw_client, import, args.no_cache)
+        },
+        Platforms::Local { import, library, output, absolute } => {
+            let library = LocalLibrary::scan(library, M3u8Output { path: output, absolute })?;
+            import::run(&library, import, args.no_cache)
+        },
+        Platforms::Beets { import, library, output, absolute, mpd } => {
+            let library = library.or_else(BeetsLibrary::default_path).ok_or(anyhow!("Unable to find the beets library, pass --library"))?;
+            let output = match mpd {
+                Some(music_dir) => BeetsOutput::Mpd { client: MpdClient::from_env()?, music_dir },
+                None => BeetsOutput::M3u8(M3u8Output { path: output, absolute }),
+            };
+            let beets = BeetsLibrary::open(library, output)?;
+            import::run(&beets, import, args.no_cache)
+        },
+        Platforms::Kodi { import, profile, format } => {
+            let kodi_client = KodiClient::from_env(profile, format)?;
+            import::run(&kodi_client, import, args.no_cache)
+        },
+        Platforms::Mpd { import } => {
+            let mpd_client = MpdClient::from_env()?;
+            import::run(&mpd_client, import, args.no_cache)
+        },
+        Platforms::Listenbrainz { xspf, name, output, public } => {
+            let pl = Playlist::from_file(xspf)?;
+            let jspf = Jspf::from_playlist(&pl, &name.unwrap_or(pl.title.clone()), public);
+            info!("Converted {} of {} tracks", jspf.playlist.track.len(), pl.tracklist.tracks.len());
 
-            if !ss_tracks.is_empty() && !no_create {
-                ss_client.create_playlist(pl_name.clone(), ss_tracks).unwrap();
-                info!("Created playlist: {pl_name}");
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, serde_json::to_string_pretty(&jspf)?)?;
+                    info!("Wrote JSPF to {:?}", path);
+                },
+                None => {
+                    let lb_client = ListenBrainzClient::from_env()?;
+                    let mbid = lb_client.create_playlist(&jspf)?;
+                    info!("Created playlist: {}", lb_client.playlist_url(&mbid));
+                },
+            }
+            Ok(())
+        },
+        Platforms::Export { platform, playlist, output } => {
+            let source = match platform {
+                ExportPlatform::Spotify => platform::spotify::read_playlist(&playlist)?,
+                ExportPlatform::Youtube => platform::youtube::read_playlist(&playlist)?,
+                ExportPlatform::Subsonic => subsonic_client().read_playlist(&playlist)?,
+            };
+            let xspf = export::to_playlist(source)?.to_xspf();
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, xspf)?;
+                    info!("Wrote XSPF to {:?}", path);
+                },
+                None => print!("{xspf}"),
+            }
+            Ok(())
+        },
+        Platforms::Lists { source, view_id, output } => {
+            let source = ListSource::parse(&source)?;
+            match view_id {
+                Some(view_id) => {
+                    let xspf = source.load(&view_id)?.to_xspf();
+                    match output {
+                        Some(path) => {
+                            std::fs::write(&path, xspf)?;
+                            info!("Wrote XSPF to {:?}", path);
+                        },
+                        None => print!("{xspf}"),
+                    }
+                },
+                None => {
+                    for view_id in source.view_ids()? {
+                        println!("{view_id}");
+                    }
+                },
             }
             Ok(())
         },
         Platforms::Webapp => {
-            Ok(webapp::serve().await?)
+            // The CLI platforms use blocking clients, so only the webapp runs
+            // inside an async runtime. Blocking clients can't be created or
+            // dropped in there, so the MusicBrainz client outlives it.
+            let mb_client = std::sync::Arc::new(MusicBrainzClient::from_env()?);
+            Ok(actix_web::rt::System::new().block_on(webapp::serve(mb_client.clone()))?)
+        },
+        Platforms::Musicbrainz { entity, mbid } => {
+            let mb_client = MusicBrainzClient::from_env()?;
+            let output = match entity {
+                MbEntity::Recording => serde_json::to_string_pretty(&mb_client.recording(&mbid)?)?,
+                MbEntity::Artist => serde_json::to_string_pretty(&mb_client.artist(&mbid)?)?,
+                MbEntity::Release => serde_json::to_string_pretty(&mb_client.release(&mbid)?)?,
+            };
+            println!("{output}");
+            Ok(())
+        },
+        Platforms::Cache { action } => {
+            let cache = ResolutionCache::open_default()?;
+            match action {
+                CacheAction::List { platform } => {
+                    for entry in cache.entries(platform.as_deref())? {
+                        println!("{}\t{}\t{}\t{}",
+                                 entry.platform, entry.key,
+                                 entry.platform_id.as_deref().unwrap_or("-"),
+                                 entry.resolved_at);
+                    }
+                },
+                CacheAction::Export { output } => {
+                    let json = serde_json::to_string_pretty(&cache.entries(None)?)?;
+                    match output {
+                        Some(path) => std::fs::write(path, json)?,
+                        None => println!("{json}"),
+                    }
+                },
+                CacheAction::Purge { platform, expired } => {
+                    let deleted = cache.purge(platform.as_deref(), expired)?;
+                    info!("Deleted {deleted} cache entries");
+                },
+            }
+            Ok(())
+        },
+        Platforms::Override { action } => {
+            let path = Overrides::default_path().ok_or(anyhow!("Unable to find a config directory, set MBZR_OVERRIDES"))?;
+            let mut overrides = Overrides::load(&path)?;
+            match action {
+                OverrideAction::Add { platform, id, mbid, title, artist } => {
+                    let mut entry = Override { mbid, title, artist, ..Default::default() };
+                    entry.ids.insert(platform, id);
+                    overrides.add(entry)?;
+                    overrides.save(&path)?;
+                    info!("Saved override to {:?}", path);
+                },
+                OverrideAction::List => {
+                    println!("{}", serde_json::to_string_pretty(overrides.entries())?);
+                },
+            }
+            Ok(())
         }
     }
 }
+
@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+use log::debug;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::mbzlists::{split_artists, Track};
+use crate::musicbrainz::{Artist, MusicBrainzClient};
+
+/// Normalize a name for loose comparisons. This lowercases, strips diacritics
+/// and collapses punctuation and whitespace so that "Beyoncé" and "beyonce"
+/// compare equal.
+pub fn normalize(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+pub fn title_matches(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// All the names the artists of a track could go by on a platform. This holds
+/// the creator string from the playlist and, for each credited artist in order,
+/// the name from the playlist and, when MusicBrainz ids are known, the artist
+/// names, sort names and aliases (in all locales) from MusicBrainz.
+#[derive(Debug, Clone)]
+pub struct ArtistNames {
+    /// Names for the whole credit, like "daft punk feat pharrell williams"
+    credit: HashSet<String>,
+    /// Names for each artist, primary artist first
+    artists: Vec<HashSet<String>>,
+    /// Name of the primary artist as credited on MusicBrainz, if looked up
+    primary_name: Option<String>,
+}
+
+impl ArtistNames {
+    /// Names from the playlist alone, with artists split from the creator
+    /// unless the track lists them separately
+    pub fn from_track(track: &Track) -> ArtistNames {
+        let mut artist_names = ArtistNames { credit: HashSet::new(), artists: vec![], primary_name: None };
+        insert(&mut artist_names.credit, &track.creator);
+
+        for artist in track.artist_list() {
+            let mut names = HashSet::new();
+            insert(&mut names, &artist);
+            if !names.is_empty() {
+                artist_names.artists.push(names);
+            }
+        }
+        artist_names
+    }
+
+    /// Collect names for the track's artists. Artist ids are taken from the
+    /// track identifiers, falling back to the artist credits of the recording.
+    /// Lookups go through the MusicBrainz client so alias sets are cached on
+    /// disk between runs. Failures are logged and only reduce the name set.
+    /// Artists found on MusicBrainz take the place of the ones split from the
+    /// creator, keeping the MusicBrainz order.
+    pub fn for_track(track: &Track, mb_client: Option<&MusicBrainzClient>) -> ArtistNames {
+        let mut artist_names = ArtistNames::from_track(track);
+
+        let Some(mb_client) = mb_client else {
+            return artist_names;
+        };
+
+        let mut artist_mbids = track.artist_mbids();
+        let mut credit_names = vec![];
+
+        if artist_mbids.is_empty() && let Some(recording_mbid) = track.recording_mbid() {
+            match mb_client.recording(&recording_mbid) {
+                Ok(recording) => {
+                    let credit = recording.artist_credit.iter()
+                        .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+                        .collect::<String>();
+                    insert(&mut artist_names.credit, &credit);
+
+                    for credit in recording.artist_credit {
+                        credit_names.push(credit.name);
+                        artist_mbids.push(credit.artist.id);
+                    }
+                },
+                Err(err) => debug!("Unable to look up recording {recording_mbid}: {err}"),
+            }
+        }
+
+        let mut mb_artists = vec![];
+        for (idx, artist_mbid) in artist_mbids.iter().enumerate() {
+            match mb_client.artist(artist_mbid) {
+                Ok(artist) => mb_artists.push((artist, credit_names.get(idx).cloned())),
+                Err(err) => debug!("Unable to look up artist {artist_mbid}: {err}"),
+            }
+        }
+
+        artist_names.set_mb_artists(mb_artists);
+        artist_names
+    }
+
+    // Artists found on MusicBrainz, in credit order and with the name they
+    // are credited as if known, replace the ones split from the creator
+    fn set_mb_artists(&mut self, mb_artists: Vec<(Artist, Option<String>)>) {
+        if mb_artists.is_empty() {
+            return;
+        }
+
+        self.primary_name = mb_artists.first().map(|(artist, credit_name)| credit_name.clone().unwrap_or(artist.name.clone()));
+        self.artists = mb_artists.iter()
+            .map(|(artist, credit_name)| {
+                let mut names = artist_names_of(artist);
+                if let Some(credit_name) = credit_name {
+                    insert(&mut names, credit_name);
+                }
+                names
+            })
+            .collect();
+    }
+
+    /// Artist names to search with where platforms take a single artist, in
+    /// order. This is the primary artist from MusicBrainz when known. Else the
+    /// whole creator comes first, since splitting it can cut an artist name
+    /// like "Earth, Wind & Fire" apart, followed by the primary artist split
+    /// from it.
+    pub fn search_names(&self, track: &Track) -> Vec<String> {
+        if let Some(primary_name) = &self.primary_name {
+            return vec![primary_name.clone()];
+        }
+
+        let mut names = vec![track.creator.clone()];
+        if let Some(first) = track.artist_list().into_iter().next() && first != track.creator {
+            names.push(first);
+        }
+        names
+    }
+
+    /// Match an artist string from a platform. This is either the whole credit
+    /// or a credit in itself, which has to include the primary artist.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.credit.contains(&normalize(name)) {
+            return true;
+        }
+
+        let mut names = split_artists(name);
+        names.push(name.to_string());
+        self.matches_artists(&names)
+    }
+
+    /// Match the list of artists of a platform track. The primary artist has
+    /// to be there while featured artists may be missing.
+    pub fn matches_artists<S: AsRef<str>>(&self, names: &[S]) -> bool {
+        let Some(primary) = self.artists.first() else {
+            return false;
+        };
+
+        names.iter().any(|name| {
+            let name = normalize(name.as_ref());
+            primary.contains(&name) || self.credit.contains(&name)
+        })
+    }
+}
+
+fn insert(names: &mut HashSet<String>, name: &str) {
+    let name = normalize(name);
+    if !name.is_empty() {
+        names.insert(name);
+    }
+}
+
+fn artist_names_of(artist: &Artist) -> HashSet<String> {
+    let mut names = HashSet::new();
+    insert(&mut names, &artist.name);
+    if let Some(sort_name) = &artist.sort_name {
+        insert(&mut names, sort_name);
+    }
+
+    for alias in &artist.aliases {
+        insert(&mut names, &alias.name);
+        if let Some(sort_name) = &alias.sort_name {
+            insert(&mut names, sort_name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(creator: &str) -> Track {
+        Track { title: "Title".to_string(), creator: creator.to_string(), ..Default::default() }
+    }
+
+    fn artist(name: &str, aliases: &[&str]) -> Artist {
+        let aliases = aliases.iter().map(|alias| serde_json::json!({ "name": alias })).collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({ "id": "x", "name": name, "aliases": aliases })).unwrap()
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize("Beyoncé"), "beyonce");
+        assert_eq!(normalize("  Sigur  Rós!"), "sigur ros");
+        assert_eq!(normalize("AC/DC"), "ac dc");
+        assert_eq!(normalize("Ｄａｆｔ　Ｐｕｎｋ"), "daft punk");
+        assert!(title_matches("Don’t Stop Me Now", "Don't stop me now!"));
+    }
+
+    #[test]
+    fn looks_up_aliases() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-matching-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("artist")).unwrap();
+        std::fs::create_dir_all(dir.join("recording")).unwrap();
+        std::fs::write(dir.join("artist").join("utada.json"), serde_json::json!({
+            "id": "utada", "name": "宇多田ヒカル", "sort-name": "Utada, Hikaru",
+            "aliases": [{ "name": "Utada Hikaru", "locale": "en" }, { "name": "Utada", "sort-name": "Utada" }],
+        }).to_string()).unwrap();
+        std::fs::write(dir.join("recording").join("rec-1.json"), serde_json::json!({
+            "id": "rec-1", "title": "First Love",
+            "artist-credit": [{ "name": "Hikki", "joinphrase": "", "artist": { "id": "utada", "name": "宇多田ヒカル" } }],
+        }).to_string()).unwrap();
+        let mb_client = MusicBrainzClient::new("http://127.0.0.1:9".to_string(), Some(dir.clone())).unwrap();
+
+        // Artist ids from the playlist
+        let mut by_artist = track("宇多田ヒカル");
+        by_artist.identifiers = vec!["https://musicbrainz.org/artist/utada".to_string()];
+        let names = ArtistNames::for_track(&by_artist, Some(&mb_client));
+        for name in ["宇多田ヒカル", "Utada Hikaru", "utada, hikaru", "Utada"] {
+            assert!(names.matches(name), "{name}");
+        }
+        assert!(!names.matches("Hikaru Utada"));
+
+        // Else the recording's credits, under the credited name
+        let mut by_recording = track("Hikki");
+        by_recording.identifiers = vec!["https://musicbrainz.org/recording/rec-1".to_string()];
+        let names = ArtistNames::for_track(&by_recording, Some(&mb_client));
+        assert!(names.matches("Utada Hikaru"));
+        assert!(names.matches("Hikki"));
+        assert_eq!(names.search_names(&by_recording), vec!["Hikki"]);
+
+        // Failed lookups leave the names from the playlist
+        let mut unknown = track("Someone");
+        unknown.identifiers = vec!["https://musicbrainz.org/artist/unknown".to_string()];
+        let names = ArtistNames::for_track(&unknown, Some(&mb_client));
+        assert!(names.matches("Someone"));
+        assert!(!names.matches("Utada"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requires_primary_artist() {
+        let names = ArtistNames::from_track(&track("Daft Punk feat. Pharrell Williams"));
+        assert!(names.matches_artists(&["Daft Punk"]));
+        assert!(names.matches("Daft Punk"));
+        assert!(!names.matches_artists(&["Pharrell Williams"]));
+        assert!(!names.matches("Pharrell Williams"));
+        assert!(!names.matches("Someone Else"));
+    }
+
+    #[test]
+    fn allows_missing_featured_artists() {
+        let names = ArtistNames::from_track(&track("Daft Punk feat. Pharrell Williams & Nile Rodgers"));
+        assert!(names.matches_artists(&["Daft Punk", "Nile Rodgers"]));
+        assert!(names.matches("Daft Punk & Pharrell Williams"));
+    }
+
+    #[test]
+    fn matches_whole_credit() {
+        let names = ArtistNames::from_track(&track("Daft Punk feat. Pharrell Williams"));
+        assert!(names.matches("Daft Punk ft Pharrell Williams"));
+
+        // The heuristic splits this, the whole credit still matches
+        let names = ArtistNames::from_track(&track("Earth, Wind & Fire"));
+        assert!(names.matches("Earth, Wind & Fire"));
+        assert!(names.matches_artists(&["Earth, Wind & Fire"]));
+        assert_eq!(names.search_names(&track("Earth, Wind & Fire")), vec!["Earth, Wind & Fire", "Earth"]);
+    }
+
+    #[test]
+    fn musicbrainz_artists_replace_split_ones() {
+        // Credited in a different order than on the playlist, with an alias
+        let track = track("Pharrell Williams x Daft Punk");
+        let mut names = ArtistNames::from_track(&track);
+        names.set_mb_artists(vec![
+            (artist("Daft Punk", &["ダフト・パンク"]), None),
+            (artist("Pharrell Williams", &[]), Some("Pharrell".to_string())),
+        ]);
+
+        assert!(names.matches_artists(&["ダフト・パンク"]));
+        assert!(names.matches("Daft Punk"));
+        assert!(!names.matches("Pharrell Williams"));
+        assert!(!names.matches("Pharrell"));
+        assert!(names.matches("Pharrell Williams x Daft Punk"));
+        assert_eq!(names.search_names(&track), vec!["Daft Punk"]);
+    }
+}
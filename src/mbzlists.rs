@@ -1,49 +1,721 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
 use url::Url;
 
-#[derive(serde::Deserialize, Debug)]
-#[serde(rename = "playlist")]
+use crate::formats;
+use crate::source::{self, ListSource};
+
+const XSPF_NS: &str = "http://xspf.org/ns/0/";
+const DEFAULT_TITLE: &str = "Untitled playlist";
+
+// Extension keys used by mbzlists and ListenBrainz in JSPF documents
+pub const JSPF_PLAYLIST_EXTENSION: &str = "https://musicbrainz.org/doc/jspf#playlist";
+pub const JSPF_TRACK_EXTENSION: &str = "https://musicbrainz.org/doc/jspf#track";
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Playlist {
     pub title: String,
+    pub creator: Option<String>,
+    pub annotation: Option<String>,
+    pub info: Option<String>,
+    pub location: Option<String>,
+    pub identifier: Option<String>,
+    pub image: Option<String>,
+    pub date: Option<String>,
+    pub license: Option<String>,
+    /// `attribution`, `link`, `meta` and `extension` elements (and elements
+    /// from other namespaces) kept as raw XML
+    pub extras: Vec<String>,
+    /// Prefixed namespaces used by the raw elements, declared on the root
+    /// when writing
+    pub namespaces: Vec<(String, String)>,
     pub tracklist: Tracklist,
 }
 
 impl Playlist {
     pub fn from_xspf(file: std::path::PathBuf) -> Result<Playlist> {
         let xspf_string = std::fs::read_to_string(file)?;
-        Ok(serde_xml_rs::from_str(&xspf_string)?)
+        Playlist::parse_xspf(&xspf_string)
+    }
+
+    pub fn from_jspf(file: std::path::PathBuf) -> Result<Playlist> {
+        let jspf_string = std::fs::read_to_string(file)?;
+        Playlist::parse_jspf(&jspf_string)
+    }
+
+    /// Read a playlist file (XSPF, JSPF, M3U/M3U8, PLS or CSV), going by the
+    /// extension and looking at the content otherwise. Formats without a
+    /// playlist title are named after the file.
+    pub fn from_file(file: std::path::PathBuf) -> Result<Playlist> {
+        let extension = file.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        let name = file.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match extension.as_deref() {
+            Some("xspf") => Playlist::from_xspf(file),
+            Some("jspf") => Playlist::from_jspf(file),
+            Some("m3u" | "m3u8") => formats::m3u::parse(&std::fs::read_to_string(file)?, &name),
+            Some("pls") => formats::pls::parse(&std::fs::read_to_string(file)?, &name),
+            Some("csv") => formats::csv::parse(&std::fs::read_to_string(file)?, &name),
+            _ => {
+                let text = std::fs::read_to_string(file)?;
+                let start = text.trim_start_matches('\u{feff}').trim_start();
+
+                if start.starts_with("#EXTM3U") {
+                    formats::m3u::parse(&text, &name)
+                } else if start.to_lowercase().starts_with("[playlist]") {
+                    formats::pls::parse(&text, &name)
+                } else {
+                    Playlist::parse(&text)
+                }
+            },
+        }
+    }
+
+    /// Parse either XSPF or JSPF text
+    pub fn parse(text: &str) -> Result<Playlist> {
+        if text.trim_start().starts_with('{') {
+            Playlist::parse_jspf(text)
+        } else {
+            Playlist::parse_xspf(text)
+        }
     }
 
     pub async fn from_url(url: &str) -> Result<Playlist> {
         let parsed = Url::parse(url)?;
         let host = parsed.host_str();
 
-        match parsed.path_segments().unwrap().last() {
-            Some(view_id) => Playlist::from_view_id(view_id, host).await,
-            None => Err(anyhow!("Malformed url: {url}"))
+        // API urls (like /api/list/<view-id>?type=jspf) are fetched as given
+        if parsed.path().starts_with("/api/") && parsed.query_pairs().any(|(key, _)| key == "type") {
+            let body = reqwest::get(parsed.as_str()).await?.error_for_status()?.text().await?;
+            return Playlist::parse(&body);
+        }
+
+        let Some(view_id) = parsed.path_segments().and_then(|mut segments| segments.next_back()).filter(|id| !id.is_empty()) else {
+            return Err(anyhow!("Malformed url: {url}"));
+        };
+
+        // A source in MBZLISTS_SOURCE takes the place of the server in the url
+        match ListSource::from_env()? {
+            Some(source) if source.is_local() => source.load(view_id),
+            Some(ListSource::Server(host)) => Playlist::from_view_id(view_id, Some(&host)).await,
+            _ => Playlist::from_view_id(view_id, host).await,
         }
     }
 
     pub async fn from_view_id(view_id: &str, host: Option<&str>) -> Result<Playlist> {
         let host = host.unwrap_or("mbzlists.com");
-        let response = reqwest::get(format!("https://{host}/api/list/{view_id}?type=xspf")).await?;
+        let response = reqwest::get(source::list_url(host, view_id)).await?.error_for_status()?;
         let bytes = response.bytes().await?;
-        let body = String::from_utf8(bytes.to_vec()).expect("body is not valid UTF8!");
+        let body = String::from_utf8(bytes.to_vec()).context("mbzlists response is not valid UTF-8")?;
+
+        Playlist::parse(&body)
+    }
+
+    /// Parse a JSPF document. Artist identifiers from the MusicBrainz track
+    /// extension are added to the track identifiers, and the playlist
+    /// extension provides the creator when there is none. Missing titles and
+    /// incomplete tracks are handled like in [`Playlist::parse_xspf`].
+    pub fn parse_jspf(text: &str) -> Result<Playlist> {
+        let jspf: JspfDocument = serde_json::from_str(text)?;
+        let jspf = jspf.playlist;
+
+        let mut creator = jspf.creator;
+        if creator.is_none() {
+            creator = jspf.extension.get(JSPF_PLAYLIST_EXTENSION)
+                .and_then(|ext| ext.get("creator"))
+                .and_then(|creator| creator.as_str())
+                .map(|creator| creator.to_string());
+        }
+
+        let tracks = jspf.track.into_iter()
+            .enumerate()
+            .filter_map(|(idx, track)| {
+                let (Some(title), Some(creator)) = (track.title.filter(|t| !t.is_empty()), track.creator.filter(|c| !c.is_empty())) else {
+                    warn!("Skipping track {} without title or creator", idx + 1);
+                    return None;
+                };
+
+                let mut identifiers = track.identifier.0;
+                let track_extension = track.extension.get(JSPF_TRACK_EXTENSION);
+                if let Some(artists) = track_extension
+                    .and_then(|ext| ext.get("artist_identifiers"))
+                    .and_then(|ids| ids.as_array()) {
+                    identifiers.extend(artists.iter().filter_map(|id| id.as_str()).map(|id| id.to_string()));
+                }
+
+                // ListenBrainz keeps the artist credit here, in credit order
+                let artists = track_extension
+                    .and_then(|ext| ext.pointer("/additional_metadata/artists"))
+                    .and_then(|artists| artists.as_array())
+                    .map(|artists| artists.iter()
+                        .filter_map(|artist| artist.get("artist_credit_name").and_then(|name| name.as_str()))
+                        .map(|name| name.to_string())
+                        .collect())
+                    .unwrap_or_default();
+
+                Some(Track {
+                    locations: track.location.0,
+                    identifiers,
+                    title,
+                    creator,
+                    artists,
+                    annotation: track.annotation,
+                    info: track.info,
+                    image: track.image,
+                    album: track.album,
+                    track_num: track.track_num,
+                    duration: track.duration,
+                    extras: vec![],
+                    extensions: track.extension,
+                })
+            })
+            .collect::<Vec<Track>>();
+
+        Ok(Playlist {
+            title: jspf.title.filter(|title| !title.is_empty()).unwrap_or_else(|| {
+                warn!("Playlist has no title, using {DEFAULT_TITLE:?}");
+                DEFAULT_TITLE.to_string()
+            }),
+            creator,
+            annotation: jspf.annotation,
+            info: jspf.info,
+            location: jspf.location,
+            identifier: jspf.identifier,
+            image: jspf.image,
+            date: jspf.date,
+            license: jspf.license,
+            tracklist: Tracklist { tracks },
+            ..Default::default()
+        })
+    }
+
+    /// Parse an XSPF document leniently. Elements are accepted with or without
+    /// the XSPF namespace and in any case (mbzlists writes `tracklist`). A
+    /// missing title or track list gets a default, and tracks without a title
+    /// or creator are skipped with a warning. Only XML syntax errors fail,
+    /// with their line and column.
+    pub fn parse_xspf(text: &str) -> Result<Playlist> {
+        // DTDs (and with them entity expansion) are rejected since lists are
+        // fetched from remote servers
+        let doc = roxmltree::Document::parse(text).map_err(|err| {
+            let pos = err.pos();
+            anyhow!("Invalid XSPF at line {}, column {}: {err}", pos.row, pos.col)
+        })?;
+        let root = doc.root_element();
+
+        if !root.tag_name().name().eq_ignore_ascii_case("playlist") {
+            return Err(anyhow!("Expected a playlist element, found {}", root.tag_name().name()));
+        }
+
+        let mut playlist = Playlist::default();
+        let mut title = None;
+        let mut tracklist = None;
+
+        for node in root.children().filter(|n| n.is_element()) {
+            match xspf_name(&node).as_str() {
+                "title" => title = Some(node_text(&node)),
+                "creator" => playlist.creator = Some(node_text(&node)),
+                "annotation" => playlist.annotation = Some(node_text(&node)),
+                "info" => playlist.info = Some(node_text(&node)),
+                "location" => playlist.location = Some(node_text(&node)),
+                "identifier" => playlist.identifier = Some(node_text(&node)),
+                "image" => playlist.image = Some(node_text(&node)),
+                "date" => playlist.date = Some(node_text(&node)),
+                "license" => playlist.license = Some(node_text(&node)),
+                "tracklist" => {
+                    let tracks = node.children()
+                        .filter(|n| n.is_element() && xspf_name(n) == "track")
+                        .filter_map(|n| Track::parse(text, &n, &mut playlist))
+                        .collect::<Vec<Track>>();
+                    tracklist.get_or_insert_with(Vec::new).extend(tracks);
+                },
+                _ => {
+                    let raw = playlist.raw_element(text, &node);
+                    playlist.extras.push(raw);
+                },
+            }
+        }
+
+        playlist.title = title.filter(|title| !title.is_empty()).unwrap_or_else(|| {
+            warn!("Playlist has no title, using {DEFAULT_TITLE:?}");
+            DEFAULT_TITLE.to_string()
+        });
+        playlist.tracklist = Tracklist {
+            tracks: tracklist.unwrap_or_else(|| {
+                warn!("Playlist has no trackList");
+                vec![]
+            }),
+        };
+        Ok(playlist)
+    }
+
+    // Raw text of an element, recording the prefixed namespaces it needs
+    fn raw_element(&mut self, text: &str, node: &roxmltree::Node) -> String {
+        for ns in node.namespaces() {
+            if let Some(prefix) = ns.name() {
+                let ns = (prefix.to_string(), ns.uri().to_string());
+                if !self.namespaces.contains(&ns) {
+                    self.namespaces.push(ns);
+                }
+            }
+        }
+
+        text[node.range()].to_string()
+    }
+
+    /// Serialize as an XSPF 1 document
+    pub fn to_xspf(&self) -> String {
+        let mut xspf = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xspf.push_str(&format!("<playlist version=\"1\" xmlns=\"{XSPF_NS}\""));
+        for (prefix, uri) in &self.namespaces {
+            xspf.push_str(&format!(" xmlns:{prefix}=\"{}\"", escape_xml(uri)));
+        }
+        xspf.push_str(">\n");
+
+        push_element(&mut xspf, 1, "title", &self.title);
+        let optional = [
+            ("creator", &self.creator),
+            ("annotation", &self.annotation),
+            ("info", &self.info),
+            ("location", &self.location),
+            ("identifier", &self.identifier),
+            ("image", &self.image),
+            ("date", &self.date),
+            ("license", &self.license),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                push_element(&mut xspf, 1, name, value);
+            }
+        }
+        for extra in &self.extras {
+            xspf.push_str(&format!("  {extra}\n"));
+        }
+
+        xspf.push_str("  <trackList>\n");
+        for track in &self.tracklist.tracks {
+            track.write_xspf(&mut xspf);
+        }
+        xspf.push_str("  </trackList>\n");
+        xspf.push_str("</playlist>\n");
+        xspf
+    }
+}
+
+// Lowercased name of XSPF elements, empty for elements from other namespaces
+fn xspf_name(node: &roxmltree::Node) -> String {
+    match node.tag_name().namespace() {
+        None | Some(XSPF_NS) => node.tag_name().name().to_lowercase(),
+        Some(_) => String::new(),
+    }
+}
+
+// Extensions written from JSPF hold a JSON object instead of XML
+fn json_extension(node: &roxmltree::Node) -> Option<(String, serde_json::Value)> {
+    let application = node.attribute("application")?;
+    if node.children().any(|n| n.is_element()) {
+        return None;
+    }
+
+    let value = serde_json::from_str::<serde_json::Value>(&node_text(node)).ok().filter(|v| v.is_object())?;
+    Some((application.to_string(), value))
+}
+
+fn node_text(node: &roxmltree::Node) -> String {
+    node.text().unwrap_or_default().trim().to_string()
+}
+
+fn push_element(xspf: &mut String, indent: usize, name: &str, value: &str) {
+    xspf.push_str(&format!("{}<{name}>{}</{name}>\n", "  ".repeat(indent), escape_xml(value)));
+}
+
+#[derive(serde::Deserialize)]
+struct JspfDocument {
+    playlist: JspfPlaylist,
+}
+
+#[derive(serde::Deserialize)]
+struct JspfPlaylist {
+    title: Option<String>,
+    creator: Option<String>,
+    annotation: Option<String>,
+    info: Option<String>,
+    location: Option<String>,
+    identifier: Option<String>,
+    image: Option<String>,
+    date: Option<String>,
+    license: Option<String>,
+    #[serde(default)]
+    extension: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    track: Vec<JspfTrack>,
+}
+
+#[derive(serde::Deserialize)]
+struct JspfTrack {
+    #[serde(default)]
+    location: OneOrMany,
+    #[serde(default)]
+    identifier: OneOrMany,
+    title: Option<String>,
+    creator: Option<String>,
+    annotation: Option<String>,
+    info: Option<String>,
+    image: Option<String>,
+    album: Option<String>,
+    #[serde(rename = "trackNum")]
+    track_num: Option<u32>,
+    duration: Option<u64>,
+    #[serde(default)]
+    extension: serde_json::Map<String, serde_json::Value>,
+}
+
+// JSPF writers disagree on whether identifier and location are lists
+#[derive(serde::Deserialize, Default)]
+#[serde(from = "serde_json::Value")]
+struct OneOrMany(Vec<String>);
+
+impl From<serde_json::Value> for OneOrMany {
+    fn from(value: serde_json::Value) -> OneOrMany {
+        match value {
+            serde_json::Value::String(s) => OneOrMany(vec![s]),
+            serde_json::Value::Array(values) => OneOrMany(
+                values.into_iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()
+            ),
+            _ => OneOrMany(vec![]),
+        }
+    }
+}
+
+/// Escape text for use in XML content and attribute values
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
 
-        Ok(serde_xml_rs::from_str(&body)?)
+// Phrases joining artists in a credit, matched case-insensitively. Bare "and"
+// and "x" are left out since they show up in too many band names.
+const JOIN_PHRASES: &[&str] = &[
+    " featuring ", " feat. ", " feat ", " ft. ", " ft ", " with ", " vs. ", " vs ", " & ", " + ", ", ", "; ", " / ",
+];
+
+/// Split an artist credit like "Daft Punk feat. Pharrell Williams & Nile
+/// Rodgers" into the artist names, in order
+pub fn split_artists(credit: &str) -> Vec<String> {
+    // Featured artists are often put in brackets
+    let credit = credit.replace(['(', ')', '[', ']'], " ");
+    let credit = credit.split_whitespace().collect::<Vec<&str>>().join(" ");
+    // ASCII lowercasing keeps byte offsets the same
+    let lower = credit.to_ascii_lowercase();
+
+    let mut artists = vec![];
+    let mut start = 0;
+    while start < credit.len() {
+        let next = JOIN_PHRASES.iter()
+            .filter_map(|phrase| lower[start..].find(phrase).map(|pos| (start + pos, phrase.len())))
+            .min();
+
+        let end = next.map_or(credit.len(), |(pos, _)| pos);
+        let name = credit[start..end].trim();
+        if !name.is_empty() && !artists.iter().any(|a: &String| a.eq_ignore_ascii_case(name)) {
+            artists.push(name.to_string());
+        }
+
+        match next {
+            Some((pos, len)) => start = pos + len,
+            None => break,
+        }
     }
+    artists
 }
 
-#[derive(serde::Deserialize, Debug)]
-#[serde(rename = "tracklist")]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Tracklist {
-    #[serde(rename = "track")]
     pub tracks: Vec<Track>,
 }
 
-#[derive(serde::Deserialize, Debug)]
-#[serde(rename = "track")]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Track {
+    pub locations: Vec<String>,
+    pub identifiers: Vec<String>,
     pub title: String,
     pub creator: String,
+    /// Credited artists in order, when the playlist has them separately (like
+    /// in ListenBrainz JSPF). Not written to XSPF, see [`Track::artist_list`].
+    pub artists: Vec<String>,
+    pub annotation: Option<String>,
+    pub info: Option<String>,
+    pub image: Option<String>,
+    pub album: Option<String>,
+    pub track_num: Option<u32>,
+    /// Duration in milliseconds
+    pub duration: Option<u64>,
+    /// `link`, `meta` and `extension` elements (and elements from other
+    /// namespaces) kept as raw XML
+    pub extras: Vec<String>,
+    /// JSPF extensions by application, like the MusicBrainz one with the
+    /// release and who added the track. Written to XSPF as `extension`
+    /// elements with the JSON as content.
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Track {
+    fn parse(text: &str, node: &roxmltree::Node, playlist: &mut Playlist) -> Option<Track> {
+        let mut track = Track::default();
+        let mut title = None;
+        let mut creator = None;
+
+        for child in node.children().filter(|n| n.is_element()) {
+            match xspf_name(&child).as_str() {
+                "location" => track.locations.push(node_text(&child)),
+                "identifier" => track.identifiers.push(node_text(&child)),
+                "title" => title = Some(node_text(&child)),
+                "creator" => creator = Some(node_text(&child)),
+                "annotation" => track.annotation = Some(node_text(&child)),
+                "info" => track.info = Some(node_text(&child)),
+                "image" => track.image = Some(node_text(&child)),
+                "album" => track.album = Some(node_text(&child)),
+                "tracknum" => track.track_num = node_text(&child).parse().ok(),
+                "duration" => track.duration = node_text(&child).parse().ok(),
+                "extension" if let Some((application, value)) = json_extension(&child) => {
+                    track.extensions.insert(application, value);
+                },
+                _ => track.extras.push(playlist.raw_element(text, &child)),
+            }
+        }
+
+        let pos = node.document().text_pos_at(node.range().start);
+        match (title.filter(|t| !t.is_empty()), creator.filter(|c| !c.is_empty())) {
+            (Some(title), Some(creator)) => {
+                track.title = title;
+                track.creator = creator;
+                Some(track)
+            },
+            (title, _) => {
+                let title = title.map(|title| format!(" {title:?}")).unwrap_or_default();
+                warn!("Skipping track{title} at line {} without title or creator", pos.row);
+                None
+            },
+        }
+    }
+
+    fn write_xspf(&self, xspf: &mut String) {
+        xspf.push_str("    <track>\n");
+        for location in &self.locations {
+            push_element(xspf, 3, "location", location);
+        }
+        for identifier in &self.identifiers {
+            push_element(xspf, 3, "identifier", identifier);
+        }
+        push_element(xspf, 3, "title", &self.title);
+        push_element(xspf, 3, "creator", &self.creator);
+
+        let optional = [
+            ("annotation", self.annotation.clone()),
+            ("info", self.info.clone()),
+            ("image", self.image.clone()),
+            ("album", self.album.clone()),
+            ("trackNum", self.track_num.map(|n| n.to_string())),
+            ("duration", self.duration.map(|d| d.to_string())),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                push_element(xspf, 3, name, &value);
+            }
+        }
+        for extra in &self.extras {
+            xspf.push_str(&format!("      {extra}\n"));
+        }
+        for (application, value) in &self.extensions {
+            xspf.push_str(&format!("      <extension application=\"{}\">{}</extension>\n", escape_xml(application), escape_xml(&value.to_string())));
+        }
+        xspf.push_str("    </track>\n");
+    }
+
+    /// Credited artists in order, primary artist first. Without separate
+    /// artist data, this splits the creator on join phrases.
+    pub fn artist_list(&self) -> Vec<String> {
+        if self.artists.is_empty() {
+            split_artists(&self.creator)
+        } else {
+            self.artists.clone()
+        }
+    }
+
+    /// MusicBrainz recording id from the track identifiers, if any
+    pub fn recording_mbid(&self) -> Option<String> {
+        self.mbids_of("recording").into_iter().next()
+    }
+
+    /// MusicBrainz artist ids from the track identifiers
+    pub fn artist_mbids(&self) -> Vec<String> {
+        self.mbids_of("artist")
+    }
+
+    // Identifiers look like https://musicbrainz.org/recording/<mbid>
+    fn mbids_of(&self, entity: &str) -> Vec<String> {
+        let pattern = format!("/{entity}/");
+        self.identifiers.iter()
+            .filter_map(|id| id.split_once(&pattern).map(|(_, mbid)| mbid.trim_matches('/').to_string()))
+            .filter(|mbid| !mbid.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/" xmlns:mb="https://musicbrainz.org/ns/">
+  <title>Rock &amp; Roll</title>
+  <creator>mbzlists</creator>
+  <annotation>Songs with &lt;brackets&gt; and "quotes"</annotation>
+  <info>https://mbzlists.com/list/abc</info>
+  <identifier>https://mbzlists.com/list/abc</identifier>
+  <date>2024-01-01T00:00:00Z</date>
+  <license>https://creativecommons.org/licenses/by/4.0/</license>
+  <meta rel="https://mbzlists.com/ns/views">12</meta>
+  <extension application="https://musicbrainz.org"><mb:public>true</mb:public></extension>
+  <trackList>
+    <track>
+      <location>https://example.com/one.flac</location>
+      <identifier>https://musicbrainz.org/recording/8f3471b5-7e6a-48da-86a9-c1c07a0f47ae</identifier>
+      <identifier>https://musicbrainz.org/artist/056e4f3e-d505-4dad-8ec1-d04f521cbb56</identifier>
+      <title>One More Time</title>
+      <creator>Daft Punk</creator>
+      <album>Discovery</album>
+      <trackNum>1</trackNum>
+      <duration>320357</duration>
+      <link rel="https://example.com/rel">https://example.com/link</link>
+    </track>
+    <track>
+      <title>Rock 'n' Roll</title>
+      <creator>Cover Band</creator>
+    </track>
+  </trackList>
+</playlist>
+"#;
+
+    #[test]
+    fn round_trip() {
+        let playlist = Playlist::parse_xspf(FULL).unwrap();
+        assert_eq!(playlist.title, "Rock & Roll");
+        assert_eq!(playlist.extras.len(), 2);
+        assert_eq!(playlist.tracklist.tracks[0].duration, Some(320357));
+        assert_eq!(playlist.tracklist.tracks[0].recording_mbid().as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+
+        let written = playlist.to_xspf();
+        let reparsed = Playlist::parse_xspf(&written).unwrap();
+        assert_eq!(playlist, reparsed);
+        assert_eq!(written, reparsed.to_xspf());
+    }
+
+    #[test]
+    fn parses_mbzlists_tracklist() {
+        let xspf = r#"<playlist><title>Mix</title><tracklist><track><title>A</title><creator>B</creator></track></tracklist></playlist>"#;
+        let playlist = Playlist::parse_xspf(xspf).unwrap();
+        assert_eq!(playlist.tracklist.tracks.len(), 1);
+
+        let written = playlist.to_xspf();
+        assert!(written.contains("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">"));
+        assert!(written.contains("<trackList>"));
+        assert_eq!(Playlist::parse_xspf(&written).unwrap(), playlist);
+    }
+
+    #[test]
+    fn escapes_text() {
+        let playlist = Playlist {
+            title: "<Tom & Jerry's \"Mix\">".to_string(),
+            tracklist: Tracklist {
+                tracks: vec![Track { title: "A & B".to_string(), creator: "C < D".to_string(), ..Default::default() }],
+            },
+            ..Default::default()
+        };
+
+        let written = playlist.to_xspf();
+        assert!(written.contains("<title>&lt;Tom &amp; Jerry&apos;s &quot;Mix&quot;&gt;</title>"));
+        assert_eq!(Playlist::parse_xspf(&written).unwrap(), playlist);
+    }
+
+    #[test]
+    fn parses_jspf() {
+        let jspf = r#"{"playlist": {
+            "title": "Mix",
+            "extension": {"https://musicbrainz.org/doc/jspf#playlist": {"creator": "someone", "public": true}},
+            "track": [
+                {"title": "One More Time", "creator": "Daft Punk",
+                 "identifier": ["https://musicbrainz.org/recording/8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"],
+                 "extension": {"https://musicbrainz.org/doc/jspf#track": {
+                     "artist_identifiers": ["https://musicbrainz.org/artist/056e4f3e-d505-4dad-8ec1-d04f521cbb56"],
+                     "added_by": "someone",
+                     "additional_metadata": {"caa_id": 1}}}},
+                {"title": "A", "creator": "B", "identifier": "https://musicbrainz.org/recording/x", "duration": 1000}
+            ]}}"#;
+
+        let playlist = Playlist::parse(jspf).unwrap();
+        assert_eq!(playlist.creator.as_deref(), Some("someone"));
+
+        let tracks = &playlist.tracklist.tracks;
+        assert_eq!(tracks[0].recording_mbid().as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+        assert_eq!(tracks[0].artist_mbids(), vec!["056e4f3e-d505-4dad-8ec1-d04f521cbb56"]);
+        assert_eq!(tracks[1].recording_mbid().as_deref(), Some("x"));
+        assert_eq!(tracks[1].duration, Some(1000));
+
+        // Extensions survive the trip through XSPF
+        let extension = &tracks[0].extensions[JSPF_TRACK_EXTENSION];
+        assert_eq!(extension["added_by"], "someone");
+        let written = playlist.to_xspf();
+        assert!(written.contains("<extension application=\"https://musicbrainz.org/doc/jspf#track\">{&quot;added_by&quot;"), "{written}");
+        assert_eq!(Playlist::parse_xspf(&written).unwrap().tracklist.tracks[0].extensions, tracks[0].extensions);
+    }
+
+    #[test]
+    fn parses_leniently() {
+        let xspf = r#"<?xml version="1.0"?>
+<Playlist xmlns="http://xspf.org/ns/0/">
+  <Unknown>ignored</Unknown>
+  <TrackList>
+    <Track><Title>Kept</Title><Creator>Artist</Creator><TRACKNUM>2</TRACKNUM></Track>
+    <track><title>No creator</title></track>
+    <track><creator>No title</creator></track>
+  </TrackList>
+</Playlist>"#;
+
+        let playlist = Playlist::parse_xspf(xspf).unwrap();
+        assert_eq!(playlist.title, DEFAULT_TITLE);
+        assert_eq!(playlist.tracklist.tracks.len(), 1);
+        assert_eq!(playlist.tracklist.tracks[0].track_num, Some(2));
+
+        let empty = Playlist::parse_xspf("<playlist><title>Empty</title><trackList/></playlist>").unwrap();
+        assert!(empty.tracklist.tracks.is_empty());
+        assert!(Playlist::parse_xspf("<playlist><title>No list</title></playlist>").unwrap().tracklist.tracks.is_empty());
+
+        let err = Playlist::parse_xspf("<playlist>\n  <title>Broken</titel>\n</playlist>").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid XSPF at line 2, column"), "{err}");
+
+        let entities = "<!DOCTYPE playlist [<!ENTITY a \"aaaaaaaaaa\"><!ENTITY b \"&a;&a;&a;&a;&a;&a;&a;&a;&a;&a;\">]>\n<playlist><title>&b;</title></playlist>";
+        assert!(Playlist::parse_xspf(entities).is_err());
+    }
+
+    #[test]
+    fn splits_artist_credits() {
+        assert_eq!(split_artists("Daft Punk feat. Pharrell Williams & Nile Rodgers"), vec!["Daft Punk", "Pharrell Williams", "Nile Rodgers"]);
+        assert_eq!(split_artists("Artist (FT. Guest)"), vec!["Artist", "Guest"]);
+        assert_eq!(split_artists("Hootie and the Blowfish"), vec!["Hootie and the Blowfish"]);
+
+        let track = Track {
+            creator: "A x B".to_string(),
+            artists: vec!["A".to_string(), "B".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(track.artist_list(), vec!["A", "B"]);
+    }
 }
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache;
+
+const DEFAULT_ROOT: &str = "https://musicbrainz.org";
+const USER_AGENT: &str = concat!(
+    "mbzlists-resolvers/", env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/lepisma/mbzlists-resolvers )"
+);
+
+// MusicBrainz data rarely changes for the fields we care about, so cached
+// responses are kept for a long time.
+const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ArtistRef {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "sort-name")]
+    pub sort_name: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ArtistCredit {
+    pub name: String,
+    #[serde(default)]
+    pub joinphrase: String,
+    pub artist: ArtistRef,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ReleaseGroup {
+    pub id: String,
+    pub title: String,
+    #[serde(rename = "primary-type")]
+    pub primary_type: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    pub date: Option<String>,
+    #[serde(rename = "release-group")]
+    pub release_group: Option<ReleaseGroup>,
+    #[serde(rename = "artist-credit", default)]
+    pub artist_credit: Vec<ArtistCredit>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Recording {
+    pub id: String,
+    pub title: String,
+    /// Length in milliseconds
+    pub length: Option<u64>,
+    #[serde(default)]
+    pub isrcs: Vec<String>,
+    #[serde(rename = "artist-credit", default)]
+    pub artist_credit: Vec<ArtistCredit>,
+    #[serde(default)]
+    pub releases: Vec<Release>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Alias {
+    pub name: String,
+    #[serde(rename = "sort-name")]
+    pub sort_name: Option<String>,
+    pub locale: Option<String>,
+    pub primary: Option<bool>,
+    #[serde(rename = "type")]
+    pub alias_type: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "sort-name")]
+    pub sort_name: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<Alias>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct IsrcLookup {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+pub struct MusicBrainzClient {
+    root: String,
+    cache_dir: Option<PathBuf>,
+    min_interval: Option<Duration>,
+    last_request: Mutex<Option<Instant>>,
+    client: reqwest::blocking::Client,
+}
+
+impl MusicBrainzClient {
+    /// Create a client for the MusicBrainz server at `root`. The public server
+    /// allows 1 request per second so we throttle ourselves there. Self-hosted
+    /// mirrors are not throttled.
+    pub fn new(root: String, cache_dir: Option<PathBuf>) -> Result<MusicBrainzClient> {
+        let root = root.trim_end_matches('/').to_string();
+        let min_interval = if Self::is_public_server(&root) { Some(Duration::from_secs(1)) } else { None };
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(USER_AGENT)
+            .build()?;
+
+        Ok(MusicBrainzClient {
+            root, cache_dir, min_interval,
+            last_request: Mutex::new(None),
+            client,
+        })
+    }
+
+    /// Build client using `MB_HOST` (defaults to the public server) and
+    /// `MBZR_CACHE_DIR` (defaults to the user cache directory).
+    pub fn from_env() -> Result<MusicBrainzClient> {
+        let root = std::env::var("MB_HOST").unwrap_or(DEFAULT_ROOT.to_string());
+        Self::new(root, default_cache_dir())
+    }
+
+    fn is_public_server(root: &str) -> bool {
+        url::Url::parse(root).ok()
+            .and_then(|u| u.host_str().map(|h| h == "musicbrainz.org" || h.ends_with(".musicbrainz.org")))
+            .unwrap_or(false)
+    }
+
+    pub fn recording(&self, mbid: &str) -> Result<Recording> {
+        self.lookup("recording", mbid, "artist-credits+isrcs+releases+release-groups")
+    }
+
+    pub fn artist(&self, mbid: &str) -> Result<Artist> {
+        self.lookup("artist", mbid, "aliases")
+    }
+
+    pub fn release(&self, mbid: &str) -> Result<Release> {
+        self.lookup("release", mbid, "artist-credits+release-groups")
+    }
+
+    /// Recordings with the given ISRC
+    pub fn recordings_by_isrc(&self, isrc: &str) -> Result<Vec<Recording>> {
+        let lookup: IsrcLookup = self.lookup("isrc", isrc, "artist-credits")?;
+        Ok(lookup.recordings)
+    }
+
+    fn lookup<T: DeserializeOwned>(&self, entity: &str, mbid: &str, inc: &str) -> Result<T> {
+        // Ids end up in cache file paths, MBIDs are hex and ISRCs alphanumeric
+        if mbid.is_empty() || !mbid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow!("Invalid id for {entity} lookup: {mbid}"));
+        }
+
+        let cache_file = self.cache_dir.as_ref().map(|dir| dir.join(entity).join(format!("{mbid}.json")));
+
+        if let Some(body) = cache_file.as_ref().and_then(read_fresh) {
+            debug!("MusicBrainz cache hit for {entity}/{mbid}");
+            return serde_json::from_str(&body).context("Failed to parse cached MusicBrainz response");
+        }
+
+        let body = self.get(&format!("{}/ws/2/{entity}/{mbid}?inc={inc}&fmt=json", self.root))?;
+        let parsed = serde_json::from_str(&body).context("Failed to parse MusicBrainz response")?;
+
+        if let Some(path) = cache_file {
+            // A failed cache write should not fail the lookup
+            if let Err(err) = write_cache(&path, &body) {
+                debug!("Unable to write MusicBrainz cache {:?}: {err}", path);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn get(&self, url: &str) -> Result<String> {
+        self.throttle();
+        debug!("MusicBrainz request: {url}");
+
+        let res = self.client.get(url).send().context("Failed to send MusicBrainz request")?;
+        let status = res.status();
+        let body = res.text().context("Failed to read MusicBrainz response body")?;
+
+        if status != reqwest::StatusCode::OK {
+            return Err(anyhow!("MusicBrainz request failed: {} - {}", status, body));
+        }
+
+        Ok(body)
+    }
+
+    fn throttle(&self) {
+        let mut last_request = self.last_request.lock().unwrap();
+
+        if let (Some(interval), Some(last)) = (self.min_interval, *last_request) {
+            let elapsed = last.elapsed();
+            if elapsed < interval {
+                std::thread::sleep(interval - elapsed);
+            }
+        }
+
+        *last_request = Some(Instant::now());
+    }
+}
+
+/// Directory for locally cached MusicBrainz data
+pub fn default_cache_dir() -> Option<PathBuf> {
+    cache::base_dir().map(|dir| dir.join("musicbrainz"))
+}
+
+fn read_fresh(path: &PathBuf) -> Option<String> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+
+    if age > CACHE_TTL {
+        None
+    } else {
+        std::fs::read_to_string(path).ok()
+    }
+}
+
+fn write_cache(path: &Path, body: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nothing listens on the discard port, so any request would fail
+    const UNREACHABLE: &str = "http://127.0.0.1:9";
+
+    #[test]
+    fn throttles_public_server_only() {
+        for root in ["https://musicbrainz.org", "https://musicbrainz.org/", "https://beta.musicbrainz.org"] {
+            let client = MusicBrainzClient::new(root.to_string(), None).unwrap();
+            assert_eq!(client.min_interval, Some(Duration::from_secs(1)), "{root}");
+        }
+
+        for root in ["http://localhost:5000", "http://mb.example.com", "https://notmusicbrainz.org", "musicbrainz.org"] {
+            let client = MusicBrainzClient::new(root.to_string(), None).unwrap();
+            assert_eq!(client.min_interval, None, "{root}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        let client = MusicBrainzClient::new(UNREACHABLE.to_string(), None).unwrap();
+
+        for mbid in ["", "../artist/x", "a/b", "8f3471b5 7e6a", "a?inc=x"] {
+            let err = client.recording(mbid).unwrap_err();
+            assert!(err.to_string().contains("Invalid id"), "{mbid}: {err}");
+        }
+    }
+
+    #[test]
+    fn reads_fresh_cache_only() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-musicbrainz-{}", std::process::id()));
+        let client = MusicBrainzClient::new(UNREACHABLE.to_string(), Some(dir.clone())).unwrap();
+        let mbid = "0383dadf-2a4e-4d10-a46a-e9e041da8eb3";
+        let path = dir.join("artist").join(format!("{mbid}.json"));
+        write_cache(&path, &format!(r#"{{"id": "{mbid}", "name": "Queen", "aliases": [{{"name": "クイーン"}}]}}"#)).unwrap();
+
+        let artist = client.artist(mbid).unwrap();
+        assert_eq!(artist.name, "Queen");
+        assert_eq!(artist.aliases[0].name, "クイーン");
+
+        // Expired entries are fetched again, which fails here
+        let expired = SystemTime::now() - CACHE_TTL - Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(expired).unwrap();
+        assert!(read_fresh(&path).is_none());
+        assert!(client.artist(mbid).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::matching;
+use crate::mbzlists::Track;
+
+/// A manual mapping from a track to platform ids. Tracks are identified by
+/// recording MBID or by title and artist. Platform ids are keyed by platform
+/// name, e.g.
+///
+/// ```toml
+/// [[override]]
+/// title = "Get Lucky"
+/// artist = "Daft Punk"
+/// spotify = "69kOkLUCkxIZYexIgSG8rq"
+/// youtube = "5NV6Rdv1a3I"
+/// ```
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
+pub struct Override {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(flatten)]
+    pub ids: BTreeMap<String, String>,
+}
+
+impl Override {
+    /// Override mapping `track` to `id` on `platform`, keyed by recording MBID
+    /// when known.
+    pub fn for_track(track: &Track, platform: &str, id: &str) -> Override {
+        let mut entry = match track.recording_mbid() {
+            Some(mbid) => Override { mbid: Some(mbid), ..Default::default() },
+            None => Override { title: Some(track.title.clone()), artist: Some(track.creator.clone()), ..Default::default() },
+        };
+        entry.ids.insert(platform.to_string(), id.to_string());
+        entry
+    }
+
+    fn matches(&self, track: &Track) -> bool {
+        if let (Some(mbid), Some(track_mbid)) = (&self.mbid, track.recording_mbid()) {
+            return *mbid == track_mbid;
+        }
+
+        match (&self.title, &self.artist) {
+            (Some(title), Some(artist)) => {
+                matching::title_matches(title, &track.title) && matching::normalize(artist) == matching::normalize(&track.creator)
+            },
+            _ => false,
+        }
+    }
+
+    fn same_track(&self, other: &Override) -> bool {
+        match (&self.mbid, &other.mbid) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => {
+                self.title.as_deref().map(matching::normalize) == other.title.as_deref().map(matching::normalize)
+                    && self.artist.as_deref().map(matching::normalize) == other.artist.as_deref().map(matching::normalize)
+            },
+            _ => false,
+        }
+    }
+}
+
+/// User maintained override mappings, read from a TOML or JSON file
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub struct Overrides {
+    #[serde(rename = "override", default)]
+    entries: Vec<Override>,
+}
+
+impl Overrides {
+    /// `MBZR_OVERRIDES` if set, else `overrides.toml` in the user config
+    /// directory.
+    pub fn default_path() -> Option<PathBuf> {
+        match std::env::var("MBZR_OVERRIDES") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => dirs::config_dir().map(|dir| dir.join("mbzlists-resolvers").join("overrides.toml")),
+        }
+    }
+
+    /// Read overrides from `path`. A missing file means no overrides.
+    pub fn load(path: &Path) -> Result<Overrides> {
+        if !path.exists() {
+            return Ok(Overrides::default());
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        if is_json(path) {
+            serde_json::from_str(&content).with_context(|| format!("Malformed overrides file {:?}", path))
+        } else {
+            toml::from_str(&content).with_context(|| format!("Malformed overrides file {:?}", path))
+        }
+    }
+
+    pub fn load_default() -> Result<Overrides> {
+        match Self::default_path() {
+            Some(path) => Self::load(&path),
+            None => Ok(Overrides::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string_pretty(self)?
+        };
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Platform id to use for the track instead of searching, if any.
+    /// Entries for the recording MBID win over title and artist ones.
+    pub fn lookup(&self, platform: &str, track: &Track) -> Option<String> {
+        let mut matching = self.entries.iter()
+            .filter(|o| o.matches(track) && o.ids.contains_key(platform))
+            .collect::<Vec<&Override>>();
+        matching.sort_by_key(|o| o.mbid.is_none());
+
+        matching.first().map(|o| o.ids[platform].clone())
+    }
+
+    /// Add an override, merging the platform ids into an existing entry for
+    /// the same track.
+    pub fn add(&mut self, entry: Override) -> Result<()> {
+        if entry.mbid.is_none() && (entry.title.is_none() || entry.artist.is_none()) {
+            return Err(anyhow!("Override needs an MBID or both title and artist"));
+        }
+
+        match self.entries.iter_mut().find(|o| o.same_track(&entry)) {
+            Some(existing) => existing.ids.extend(entry.ids),
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Override] {
+        &self.entries
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, creator: &str, mbid: Option<&str>) -> Track {
+        Track {
+            title: title.to_string(),
+            creator: creator.to_string(),
+            identifiers: mbid.map(|mbid| format!("https://musicbrainz.org/recording/{mbid}")).into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn entry(mbid: Option<&str>, title: Option<&str>, artist: Option<&str>, ids: &[(&str, &str)]) -> Override {
+        Override {
+            mbid: mbid.map(str::to_string),
+            title: title.map(str::to_string),
+            artist: artist.map(str::to_string),
+            ids: ids.iter().map(|(platform, id)| (platform.to_string(), id.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_tracks() {
+        let by_mbid = entry(Some("abc-123"), None, None, &[]);
+        assert!(by_mbid.matches(&track("Anything", "Anyone", Some("abc-123"))));
+        assert!(!by_mbid.matches(&track("Anything", "Anyone", Some("def-456"))));
+        assert!(!by_mbid.matches(&track("Anything", "Anyone", None)));
+
+        let by_name = entry(None, Some("Halo"), Some("Beyoncé"), &[]);
+        assert!(by_name.matches(&track("halo", "Beyonce", None)));
+        assert!(by_name.matches(&track("Halo!", "BEYONCÉ", Some("abc-123"))));
+        assert!(!by_name.matches(&track("Halo", "Beyoncé feat. Someone", None)));
+        assert!(!by_name.matches(&track("Halo (Live)", "Beyoncé", None)));
+
+        // A differing MBID settles it even when the names match
+        let both = entry(Some("abc-123"), Some("Halo"), Some("Beyoncé"), &[]);
+        assert!(!both.matches(&track("Halo", "Beyoncé", Some("def-456"))));
+        assert!(both.matches(&track("Halo", "Beyoncé", None)));
+    }
+
+    #[test]
+    fn merges_added_ids() {
+        let mut overrides = Overrides::default();
+        overrides.add(entry(None, Some("Halo"), Some("Beyoncé"), &[("spotify", "sp-1")])).unwrap();
+        overrides.add(entry(None, Some("halo"), Some("Beyonce"), &[("deezer", "dz-1")])).unwrap();
+        overrides.add(entry(Some("abc-123"), None, None, &[("spotify", "sp-2")])).unwrap();
+        overrides.add(entry(Some("abc-123"), None, None, &[("spotify", "sp-3")])).unwrap();
+        assert!(overrides.add(entry(None, Some("Halo"), None, &[("spotify", "sp-4")])).is_err());
+
+        assert_eq!(overrides.entries().len(), 2);
+        assert_eq!(overrides.lookup("spotify", &track("Halo", "Beyoncé", None)).as_deref(), Some("sp-1"));
+        assert_eq!(overrides.lookup("deezer", &track("Halo", "Beyoncé", None)).as_deref(), Some("dz-1"));
+        assert_eq!(overrides.lookup("spotify", &track("Halo", "Beyoncé", Some("abc-123"))).as_deref(), Some("sp-3"));
+        assert_eq!(overrides.lookup("tidal", &track("Halo", "Beyoncé", None)), None);
+    }
+
+    #[test]
+    fn round_trips_files() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-overrides-{}", std::process::id()));
+        let mut overrides = Overrides::default();
+        overrides.add(Override::for_track(&track("Get Lucky", "Daft Punk", None), "spotify", "69kOkLUCkxIZYexIgSG8rq")).unwrap();
+        overrides.add(Override::for_track(&track("Halo", "Beyoncé", Some("abc-123")), "youtube", "bnVUHWCynig")).unwrap();
+
+        for name in ["overrides.toml", "overrides.json"] {
+            let path = dir.join(name);
+            overrides.save(&path).unwrap();
+            let loaded = Overrides::load(&path).unwrap();
+
+            assert_eq!(format!("{:?}", loaded.entries()), format!("{:?}", overrides.entries()), "{name}");
+            assert_eq!(loaded.lookup("spotify", &track("get lucky", "Daft Punk", None)).as_deref(), Some("69kOkLUCkxIZYexIgSG8rq"));
+        }
+
+        let toml = std::fs::read_to_string(dir.join("overrides.toml")).unwrap();
+        assert!(toml.contains("[[override]]"), "{toml}");
+        assert!(Overrides::load(&dir.join("missing.toml")).unwrap().entries().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
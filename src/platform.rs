@@ -1,3 +1,17 @@
 pub mod subsonic;
+pub mod ampache;
+pub mod beets;
+pub mod media_browser;
+pub mod jellyfin;
+pub mod plex;
+pub mod emby;
+pub mod funkwhale;
+pub mod kodi;
+pub mod listenbrainz;
+pub mod local;
+pub mod mpd;
 pub mod spotify;
 pub mod youtube;
+pub mod deezer;
+pub mod tidal;
+pub mod apple_music;
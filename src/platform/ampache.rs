@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+
+// Version of the API to talk, responses with lists use the 6.x layout
+const API_VERSION: &str = "6.0.0";
+// Ampache advanced search operator for exact text matches
+const OPERATOR_IS: &str = "4";
+
+// Ids are strings in the JSON API but older servers send numbers
+fn string_or_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match <serde_json::Value as serde::Deserialize>::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        other => Err(serde::de::Error::custom(format!("Invalid id: {other}"))),
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct AmpacheRef {
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct AmpacheSong {
+    #[serde(deserialize_with = "string_or_number")]
+    id: String,
+    title: String,
+    artist: Option<AmpacheRef>,
+    album: Option<AmpacheRef>,
+    /// Duration in seconds
+    time: Option<u64>,
+    year: Option<u32>,
+    mbid: Option<String>,
+}
+
+impl From<AmpacheSong> for Candidate {
+    fn from(song: AmpacheSong) -> Candidate {
+        Candidate {
+            id: song.id,
+            title: song.title,
+            artist: song.artist.map(|a| a.name).unwrap_or_default(),
+            album: song.album.map(|a| a.name),
+            duration: song.time,
+            year: song.year.filter(|y| *y != 0),
+            recording_mbid: song.mbid.filter(|mbid| !mbid.is_empty()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SongsResponse {
+    #[serde(default)]
+    song: Vec<AmpacheSong>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct AmpachePlaylist {
+    #[serde(deserialize_with = "string_or_number")]
+    id: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PlaylistsResponse {
+    #[serde(default)]
+    playlist: Vec<AmpachePlaylist>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct HandshakeResponse {
+    auth: String,
+}
+
+pub struct AmpacheClient {
+    root: String,
+    auth: String,
+    client: reqwest::blocking::Client,
+}
+
+impl AmpacheClient {
+    /// Connect using `AMPACHE_HOST` and either `AMPACHE_API_KEY` or
+    /// `AMPACHE_USER` with `AMPACHE_PASS`.
+    pub fn from_env() -> Result<AmpacheClient> {
+        let root = std::env::var("AMPACHE_HOST").context("AMPACHE_HOST not set")?;
+
+        match std::env::var("AMPACHE_API_KEY") {
+            Ok(api_key) => Self::handshake(root, &api_key, None),
+            Err(_) => {
+                let user = std::env::var("AMPACHE_USER").context("Neither AMPACHE_API_KEY nor AMPACHE_USER set")?;
+                let password = std::env::var("AMPACHE_PASS").context("AMPACHE_PASS not set")?;
+                Self::login(root, &user, &password)
+            },
+        }
+    }
+
+    /// Password logins send sha256(timestamp + sha256(password)) instead of
+    /// the password itself.
+    pub fn login(root: String, user: &str, password: &str) -> Result<AmpacheClient> {
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs().to_string();
+        let key = hex(&Sha256::digest(password.as_bytes()));
+        let passphrase = hex(&Sha256::digest(format!("{timestamp}{key}").as_bytes()));
+
+        Self::handshake(root, &passphrase, Some((user, &timestamp)))
+    }
+
+    fn handshake(root: String, auth: &str, user: Option<(&str, &str)>) -> Result<AmpacheClient> {
+        let mut ampache_client = AmpacheClient {
+            root: root.trim_end_matches('/').to_string(),
+            auth: String::new(),
+            client: reqwest::blocking::Client::new(),
+        };
+
+        let mut params = vec![("auth", auth), ("version", API_VERSION)];
+        if let Some((user, timestamp)) = user {
+            params.extend([("user", user), ("timestamp", timestamp)]);
+        }
+
+        let handshake: HandshakeResponse = ampache_client.call("handshake", &params).context("Ampache login failed")?;
+        ampache_client.auth = handshake.auth;
+        Ok(ampache_client)
+    }
+
+    fn call<T: serde::de::DeserializeOwned>(&self, action: &str, params: &[(&str, &str)]) -> Result<T> {
+        let mut request = self.client.get(format!("{}/server/json.server.php", self.root))
+            .query(&[("action", action)])
+            .query(params);
+        if !self.auth.is_empty() {
+            request = request.query(&[("auth", &self.auth)]);
+        }
+
+        let res = request.send().context("Failed to send Ampache request")?;
+        let status = res.status();
+        let body = res.text().context("Failed to read Ampache response body")?;
+
+        if !status.is_success() {
+            return Err(anyhow!("Ampache request failed: {} - {}", status, body));
+        }
+
+        // Errors come with a 200 status
+        let json: serde_json::Value = serde_json::from_str(&body).context("Failed to parse Ampache JSON response")?;
+        if let Some(error) = json.get("error") {
+            return Err(anyhow!("Ampache API error: {}", error.get("errorMessage").unwrap_or(error)));
+        }
+
+        serde_json::from_value(json).context("Unexpected Ampache response")
+    }
+
+    fn find_playlist(&self, name: &str) -> Result<Option<String>> {
+        let playlists: PlaylistsResponse = self.call("playlists", &[("filter", name), ("exact", "1")])?;
+        Ok(playlists.playlist.into_iter().find(|p| p.name == name).map(|p| p.id))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl Target for AmpacheClient {
+    fn override_platform(&self) -> &'static str {
+        "ampache"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("ampache:{}", self.root)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        let songs: SongsResponse = self.call("search_songs", &[("filter", query), ("limit", "10")])?;
+        Ok(songs.song.into_iter().map(Candidate::from).collect())
+    }
+
+    /// Look the recording MBID up with an advanced search first, then search
+    /// by title and leave the artist to the matcher.
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(mbid) = track.recording_mbid() {
+            let songs: SongsResponse = self.call("advanced_search", &[
+                ("type", "song"),
+                ("operator", "and"),
+                ("rule_1", "mbid"),
+                ("rule_1_operator", OPERATOR_IS),
+                ("rule_1_input", &mbid),
+            ])?;
+            if !songs.song.is_empty() {
+                return Ok(songs.song.into_iter().map(Candidate::from).collect());
+            }
+        }
+
+        self.search(&track.title)
+    }
+
+    /// Add the songs to an existing playlist with the same name, else create
+    /// a new one.
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        let (playlist_id, existing) = match self.find_playlist(name)? {
+            Some(playlist_id) => {
+                let songs: SongsResponse = self.call("playlist_songs", &[("filter", &playlist_id)])?;
+                (playlist_id, songs.song.into_iter().map(|s| s.id).collect::<Vec<String>>())
+            },
+            None => {
+                let created: serde_json::Value = self.call("playlist_create", &[("name", name), ("type", "private")])?;
+                // Some versions wrap the new playlist in a list
+                let playlist = created.get("playlist").and_then(|p| p.get(0)).unwrap_or(&created);
+                let playlist: AmpachePlaylist = serde_json::from_value(playlist.clone()).context("Unexpected playlist_create response")?;
+                (playlist.id, vec![])
+            },
+        };
+
+        for id in ids.iter().filter(|id| !existing.contains(id)) {
+            self.call::<serde_json::Value>("playlist_add_song", &[("filter", &playlist_id), ("song", id)])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_songs() {
+        let songs: SongsResponse = serde_json::from_str(r#"{"song": [
+            {"id": "12", "title": "Get Lucky", "artist": {"id": "3", "name": "Daft Punk"}, "album": {"id": "4", "name": "Random Access Memories"},
+             "time": 369, "year": 2013, "mbid": "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"},
+            {"id": 13, "title": "Untagged", "time": 125, "year": 0, "mbid": ""}
+        ]}"#).unwrap();
+        let candidates = songs.song.into_iter().map(Candidate::from).collect::<Vec<Candidate>>();
+
+        assert_eq!(candidates[0].id, "12");
+        assert_eq!(candidates[0].artist, "Daft Punk");
+        assert_eq!(candidates[0].album.as_deref(), Some("Random Access Memories"));
+        assert_eq!((candidates[0].duration, candidates[0].year), (Some(369), Some(2013)));
+        assert_eq!(candidates[0].recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+
+        // Older servers send numeric ids and zero or empty missing values
+        assert_eq!(candidates[1].id, "13");
+        assert_eq!((&candidates[1].artist, &candidates[1].album), (&String::new(), &None));
+        assert_eq!((candidates[1].year, &candidates[1].recording_mbid), (None, &None));
+
+        let empty: SongsResponse = serde_json::from_str("{}").unwrap();
+        assert!(empty.song.is_empty());
+        assert!(serde_json::from_str::<SongsResponse>(r#"{"song": [{"id": null, "title": "x"}]}"#).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use rusqlite::{Connection, OpenFlags};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+use crate::platform::local::{file_location, FileIndex, M3u8Output};
+use crate::platform::mpd::MpdClient;
+
+/// Where resolved files go
+pub enum BeetsOutput {
+    M3u8(M3u8Output),
+    /// Stored playlist on MPD. Paths are made relative to `music_dir`, the
+    /// MPD music directory.
+    Mpd { client: MpdClient, music_dir: PathBuf },
+}
+
+/// Items in a beets library database
+pub struct BeetsLibrary {
+    db_path: PathBuf,
+    index: FileIndex,
+    output: BeetsOutput,
+}
+
+impl BeetsLibrary {
+    /// Default database location as used by beets, under `BEETSDIR` or
+    /// ~/.config/beets
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var("BEETSDIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => dirs::home_dir()?.join(".config").join("beets"),
+        };
+        Some(dir.join("library.db"))
+    }
+
+    pub fn open(db_path: PathBuf, output: BeetsOutput) -> Result<BeetsLibrary> {
+        // Read only so a running beets import is never disturbed
+        let conn = Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Unable to open beets library {:?}", db_path))?;
+
+        let mut stmt = conn.prepare("SELECT path, title, artist, album, length, year, mb_trackid FROM items")?;
+        let files = stmt.query_map([], |row| {
+            // Paths are stored as raw bytes (as text in older databases)
+            let path = row.get_ref(0)?.as_bytes()?.to_vec();
+            let year: Option<u32> = row.get(5)?;
+            let mb_trackid: Option<String> = row.get(6)?;
+            let album: Option<String> = row.get(3)?;
+
+            Ok(Candidate {
+                id: Path::new(std::ffi::OsStr::from_bytes(&path)).to_string_lossy().to_string(),
+                title: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                artist: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                album: album.filter(|a| !a.is_empty()),
+                duration: row.get::<_, Option<f64>>(4)?.map(|l| l as u64),
+                // Beets uses 0 and the empty string for missing values
+                year: year.filter(|y| *y != 0),
+                recording_mbid: mb_trackid.filter(|id| !id.is_empty()),
+            })
+        })?.collect::<rusqlite::Result<Vec<Candidate>>>()?;
+
+        info!("Read {} items from {:?}", files.len(), db_path);
+        Ok(BeetsLibrary { db_path, index: FileIndex::new(files), output })
+    }
+}
+
+impl Target for BeetsLibrary {
+    fn override_platform(&self) -> &'static str {
+        "beets"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("beets:{}", self.db_path.display())
+    }
+
+    fn offline(&self) -> bool {
+        true
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        Ok(self.index.search(query))
+    }
+
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        Ok(self.index.candidates(track))
+    }
+
+    fn location(&self, id: &str) -> Option<String> {
+        file_location(id)
+    }
+
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        match &self.output {
+            BeetsOutput::M3u8(output) => output.write(name, &ids, &self.index),
+            BeetsOutput::Mpd { client, music_dir } => client.create_playlist(name, mpd_uris(&ids, music_dir)?),
+        }
+    }
+}
+
+// MPD identifies songs by their path in the music directory
+fn mpd_uris(paths: &[String], music_dir: &Path) -> Result<Vec<String>> {
+    paths.iter()
+        .map(|path| {
+            Path::new(path).strip_prefix(music_dir)
+                .map(|uri| uri.to_string_lossy().to_string())
+                .map_err(|_| anyhow!("{path} is not in the MPD music directory {:?}", music_dir))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_libraries() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-beets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("library.db");
+
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, path BLOB, title TEXT, artist TEXT, album TEXT,
+                                 length REAL, year INTEGER, mb_trackid TEXT);
+             INSERT INTO items (path, title, artist, album, length, year, mb_trackid) VALUES
+                 (CAST('/music/Daft Punk/08 Get Lucky.flac' AS BLOB), 'Get Lucky', 'Daft Punk', 'Random Access Memories',
+                  369.6, 2013, '8f3471b5-7e6a-48da-86a9-c1c07a0f47ae'),
+                 ('/music/misc/untagged.mp3', 'Untagged', '', '', 125.0, 0, '');"
+        ).unwrap();
+        drop(conn);
+
+        let output = M3u8Output { path: Some(dir.join("Mix.m3u8")), absolute: false };
+        let library = BeetsLibrary::open(db_path, BeetsOutput::M3u8(output)).unwrap();
+
+        let lucky = library.index.get("/music/Daft Punk/08 Get Lucky.flac").unwrap();
+        assert_eq!(lucky.duration, Some(369));
+        assert_eq!(lucky.year, Some(2013));
+        assert_eq!(lucky.recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+        let untagged = library.index.get("/music/misc/untagged.mp3").unwrap();
+        assert_eq!((&untagged.album, untagged.year, &untagged.recording_mbid), (&None, None, &None));
+
+        let ids = vec!["/music/Daft Punk/08 Get Lucky.flac".to_string(), "/music/misc/untagged.mp3".to_string()];
+        library.create_playlist("Mix", ids.clone()).unwrap();
+        let m3u8 = std::fs::read_to_string(dir.join("Mix.m3u8")).unwrap();
+        let relative_root = relative_depth(&dir);
+        assert_eq!(m3u8, format!(
+            "#EXTM3U\n#PLAYLIST:Mix\n\
+             #EXTINF:369,Daft Punk - Get Lucky\n{relative_root}music/Daft Punk/08 Get Lucky.flac\n\
+             #EXTINF:125, - Untagged\n{relative_root}music/misc/untagged.mp3\n"
+        ));
+
+        assert_eq!(mpd_uris(&ids, Path::new("/music")).unwrap(), vec!["Daft Punk/08 Get Lucky.flac", "misc/untagged.mp3"]);
+        assert_eq!(mpd_uris(&ids, Path::new("/music/")).unwrap(), vec!["Daft Punk/08 Get Lucky.flac", "misc/untagged.mp3"]);
+        assert!(mpd_uris(&ids, Path::new("/music/Daft Punk")).is_err());
+        assert!(mpd_uris(&ids, Path::new("/mus")).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // `../` for every component of the canonical `dir`, to get to the root
+    fn relative_depth(dir: &Path) -> String {
+        "../".repeat(dir.canonicalize().unwrap().components().count() - 1)
+    }
+}
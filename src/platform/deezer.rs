@@ -0,0 +1,310 @@
+use actix_session::Session;
+use actix_web::{get, web, error, HttpResponse, Responder};
+use log::{debug, warn};
+use url::Url;
+use anyhow::{Result, Context, anyhow};
+use askama::Template;
+
+use crate::{matching::{self, ArtistNames}, mbzlists::{self, Track}, musicbrainz::MusicBrainzClient, webapp::{self, PlCreatePageTemplate, PlCreatedPageTemplate}};
+
+
+const API_ROOT: &str = "https://api.deezer.com";
+const PLATFORM: &str = "deezer";
+// Number of tracks added to a playlist per request
+const BATCH_SIZE: usize = 50;
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DeezerArtist {
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DeezerTrack {
+    id: u64,
+    title: String,
+    artist: DeezerArtist,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct DeezerAPIError {
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
+    #[serde(default)]
+    code: u32,
+}
+
+// Error code for lookups (like by ISRC) that found nothing
+const NO_DATA: u32 = 800;
+
+impl std::fmt::Display for DeezerAPIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deezer API error ({}): {}", self.error_type, self.message)
+    }
+}
+
+impl std::error::Error for DeezerAPIError {}
+
+// Deezer reports errors with a 200 status and an `error` object in the body
+#[derive(serde::Deserialize, Debug)]
+#[serde(untagged)]
+enum DeezerResponse<T> {
+    Error { error: DeezerAPIError },
+    Success(T),
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TracksSearchResult {
+    data: Vec<DeezerTrack>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct CreatedPlaylist {
+    id: u64,
+}
+
+async fn send<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    let res = request.send().await.context("Failed to send Deezer request")?;
+
+    let status = res.status();
+    let body = res.text().await.context("Failed to read Deezer response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Deezer request failed: {} - {}", status, body));
+    }
+
+    parse_response(&body)
+}
+
+fn parse_response<T: serde::de::DeserializeOwned>(body: &str) -> Result<T> {
+    match serde_json::from_str(body).context("Failed to parse Deezer JSON response")? {
+        DeezerResponse::Success(value) => Ok(value),
+        DeezerResponse::Error { error } => {
+            debug!("{:?}", error);
+            anyhow::bail!(error);
+        },
+    }
+}
+
+async fn api_get<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    send(reqwest::Client::new().get(url)).await
+}
+
+async fn api_post<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    send(reqwest::Client::new().post(url)).await
+}
+
+/// Resolve by ISRC first since that identifies the exact recording, then fall
+/// back to an advanced search on artist and title. Gives `None` when nothing
+/// matched, errors (like exceeded quotas) are returned as such.
+async fn resolve(track: &Track, isrcs: &[String], artist_names: &ArtistNames, access_token: &str) -> Result<Option<DeezerTrack>> {
+    for isrc in isrcs {
+        match api_get::<DeezerTrack>(&format!("{API_ROOT}/track/isrc:{isrc}?access_token={access_token}")).await {
+            Ok(dz_track) => return Ok(Some(dz_track)),
+            Err(err) if err.downcast_ref::<DeezerAPIError>().is_some_and(|err| err.code == NO_DATA) => {
+                debug!("No Deezer track for ISRC {isrc}");
+            },
+            Err(err) => return Err(err),
+        }
+    }
+
+    for artist in artist_names.search_names(track) {
+        if let Some(found_track) = search(track, &artist, artist_names, access_token).await? {
+            return Ok(Some(found_track));
+        }
+    }
+    Ok(None)
+}
+
+async fn search(track: &Track, artist: &str, artist_names: &ArtistNames, access_token: &str) -> Result<Option<DeezerTrack>> {
+    let query = urlencoding::encode(&format!("artist:\"{artist}\" track:\"{}\"", track.title)).to_string();
+    let result: TracksSearchResult = api_get(&format!("{API_ROOT}/search/track?q={query}&access_token={access_token}")).await?;
+
+    let Some(found_track) = result.data.into_iter().next() else {
+        debug!("No results for {:?} with artist {artist}", track);
+        return Ok(None);
+    };
+
+    if matching::title_matches(&found_track.title, &track.title) && artist_names.matches(&found_track.artist.name) {
+        Ok(Some(found_track))
+    } else {
+        debug!("Error in matching: {:?}", found_track);
+        Ok(None)
+    }
+}
+
+async fn create_playlist(name: &str, track_ids: Vec<String>, access_token: &str) -> Result<String> {
+    let title = urlencoding::encode(name);
+    let playlist: CreatedPlaylist = api_post(&format!("{API_ROOT}/user/me/playlists?title={title}&access_token={access_token}")).await?;
+
+    for batch in track_ids.chunks(BATCH_SIZE) {
+        let songs = batch.join(",");
+        // Returns a bare `true` on success
+        api_post::<bool>(&format!("{API_ROOT}/playlist/{}/tracks?songs={songs}&access_token={access_token}", playlist.id)).await?;
+    }
+
+    Ok(format!("https://www.deezer.com/playlist/{}", playlist.id))
+}
+
+async fn get_access_token(auth_code: &str) -> Result<String> {
+    let app_id = std::env::var("DEEZER_APP_ID").context("Missing DEEZER_APP_ID env variable")?;
+    let secret = std::env::var("DEEZER_SECRET").context("Missing DEEZER_SECRET env variable")?;
+
+    let url = Url::parse_with_params(
+        "https://connect.deezer.com/oauth/access_token.php",
+        &[("app_id", app_id.as_str()), ("secret", &secret), ("code", auth_code), ("output", "json")],
+    )?;
+
+    let res = reqwest::get(url).await.context("Failed to send token request")?;
+
+    let status = res.status();
+    let body = res.text().await.context("Failed to read response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Token exchange failed: {} - {}", status, body));
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&body).context("Failed to parse JSON response")?;
+
+    let token = json
+        .get("access_token")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing access_token in response: {}", json))?;
+
+    Ok(token.to_string())
+}
+
+#[derive(serde::Deserialize)]
+struct LoginQuery {
+    mbzlists_url: Option<String>,
+}
+
+#[get("/deezer/login")]
+pub async fn login(query: web::Query<LoginQuery>, session: Session) -> Result<impl Responder, error::Error> {
+    let app_id = std::env::var("DEEZER_APP_ID").map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Missing DEEZER_APP_ID env variable"))
+    })?;
+    let redirect_uri = std::env::var("DEEZER_REDIRECT_URI").map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Missing DEEZER_REDIRECT_URI env variable"))
+    })?;
+
+    if let Some(mbzlists_url) = &query.mbzlists_url {
+        session.insert("mbzlists_url", mbzlists_url).map_err(|_| {
+            error::ErrorInternalServerError(anyhow!("Unable to set session variable `mbzlists_url`"))
+        })?;
+    }
+
+    let auth_url = Url::parse_with_params(
+        "https://connect.deezer.com/oauth/auth.php",
+        &[
+            ("app_id", &app_id),
+            ("redirect_uri", &redirect_uri),
+            ("perms", &"basic_access,manage_library".to_string()),
+        ],
+    ).map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Unable to create auth_url"))
+    })?;
+
+    Ok(HttpResponse::Found()
+        .append_header(("Location", auth_url.to_string()))
+        .finish())
+}
+
+#[derive(serde::Deserialize)]
+struct AuthQuery {
+    code: String,
+}
+
+#[get("/deezer/callback")]
+pub async fn callback(query: web::Query<AuthQuery>, session: Session) -> Result<impl Responder, error::Error> {
+    let access_token = get_access_token(&query.code).await.map_err(error::ErrorInternalServerError)?;
+
+    // Make sure the token works before saving it
+    api_get::<serde::de::IgnoredAny>(&format!("{API_ROOT}/user/me?access_token={access_token}")).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    session.insert("deezer_access_token", &access_token).map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Unable to set session variable `deezer_access_token`"))
+    })?;
+
+    if let Some(mbzlists_url) = session.get::<String>("mbzlists_url").unwrap_or(None) {
+        let create_url = format!("/deezer/create?mbzlists_url={}", mbzlists_url);
+        return Ok(HttpResponse::Found().append_header(("Location", create_url)).finish());
+    }
+
+    let body = (PlCreatePageTemplate {
+        app_name: "Deezer",
+        app_slug: "deezer",
+    })
+        .render()
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[derive(serde::Deserialize)]
+struct CreateQuery {
+    mbzlists_url: String,
+}
+
+#[get("/deezer/create")]
+pub async fn create(query: web::Query<CreateQuery>, session: Session, mb_client: web::Data<MusicBrainzClient>) -> Result<impl Responder, error::Error> {
+    let mbzlists_url = query.mbzlists_url.clone();
+    let access_token: Option<String> = session.get("deezer_access_token").unwrap_or(None);
+
+    let Some(access_token) = access_token else {
+        return Ok(HttpResponse::Found()
+            .append_header(("Location", format!("/deezer/login?mbzlists_url={mbzlists_url}")))
+            .finish());
+    };
+
+    let playlist = mbzlists::Playlist::from_url(&mbzlists_url).await.map_err(error::ErrorInternalServerError)?;
+
+    let dz_track_ids = webapp::resolve_tracks(PLATFORM, PLATFORM, mb_client, playlist.tracklist.tracks, true, async |track: &Track, isrcs: &[String], artist_names: &ArtistNames| {
+        Ok(resolve(track, isrcs, artist_names, &access_token).await?.map(|dz_track| dz_track.id.to_string()))
+    }).await?;
+
+    // Only ids from overrides can be malformed
+    let dz_track_ids = dz_track_ids.into_iter().filter(|id| {
+        let valid = id.parse::<u64>().is_ok();
+        if !valid {
+            warn!("Invalid Deezer track id in overrides: {id}");
+        }
+        valid
+    }).collect();
+
+    let playlist_url = create_playlist(&playlist.title, dz_track_ids, &access_token).await.map_err(error::ErrorInternalServerError)?;
+
+    let body = (PlCreatedPageTemplate {
+        app_name: "Deezer",
+        playlist_url: &playlist_url,
+    })
+        .render()
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_responses() {
+        let dz_track: DeezerTrack = parse_response(r#"{"id": 67238735, "title": "Get Lucky", "isrc": "USQX91300108", "artist": {"id": 27, "name": "Daft Punk"}}"#).unwrap();
+        assert_eq!((dz_track.id, dz_track.title.as_str(), dz_track.artist.name.as_str()), (67238735, "Get Lucky", "Daft Punk"));
+
+        let result: TracksSearchResult = parse_response(r#"{"data": [], "total": 0}"#).unwrap();
+        assert!(result.data.is_empty());
+
+        // Errors come with a 200 status
+        let err = parse_response::<DeezerTrack>(r#"{"error": {"type": "DataException", "message": "no data", "code": 800}}"#).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeezerAPIError>().map(|err| err.code), Some(NO_DATA));
+        assert_eq!(err.to_string(), "Deezer API error (DataException): no data");
+
+        let err = parse_response::<DeezerTrack>(r#"{"error": {"type": "Exception", "message": "Quota limit exceeded", "code": 4}}"#).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeezerAPIError>().map(|err| err.code), Some(4));
+
+        assert!(parse_response::<DeezerTrack>(r#"{"id": "not a track"}"#).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use reqwest::blocking::RequestBuilder;
+
+use super::media_browser::{self, MediaBrowserClient, Server};
+
+pub struct Emby;
+
+pub type EmbyClient = MediaBrowserClient<Emby>;
+
+impl Server for Emby {
+    const NAME: &'static str = "Emby";
+    const PLATFORM: &'static str = "emby";
+    const ENV_PREFIX: &'static str = "EMBY";
+    const ROUTE_PREFIX: &'static str = "/emby";
+
+    // Unlike Jellyfin, Emby wants the client description in its own header
+    // and the token separately.
+    fn authorize(request: RequestBuilder, token: &str) -> RequestBuilder {
+        let request = request.header("X-Emby-Authorization", media_browser::client_description());
+        if token.is_empty() { request } else { request.header("X-Emby-Token", token) }
+    }
+
+    fn login_body(request: RequestBuilder, user: &str, password: &str) -> RequestBuilder {
+        request.form(&[("Username", user), ("Pw", password)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorizes_requests() {
+        let client = reqwest::blocking::Client::new();
+
+        let request = Emby::authorize(client.get("http://emby/emby/Users"), "").build().unwrap();
+        assert!(request.headers()["X-Emby-Authorization"].to_str().unwrap().starts_with("MediaBrowser Client=\"mbzlists-resolvers\""));
+        assert!(request.headers().get("X-Emby-Token").is_none());
+
+        let request = Emby::authorize(client.get("http://emby/emby/Users"), "abc").build().unwrap();
+        assert_eq!(request.headers()["X-Emby-Token"], "abc");
+
+        let request = Emby::login_body(client.post("http://emby/emby/Users/AuthenticateByName"), "me", "p&ss").build().unwrap();
+        assert_eq!(request.body().and_then(|body| body.as_bytes()), Some("Username=me&Pw=p%26ss".as_bytes()));
+    }
+}
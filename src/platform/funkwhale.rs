@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::RequestBuilder;
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhaleArtist {
+    name: String,
+}
+
+// Funkwhale 2 replaced the single artist with a list of credits
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhaleCredit {
+    artist: FunkwhaleArtist,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhaleAlbum {
+    title: String,
+    release_date: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhaleUpload {
+    /// Duration in seconds
+    duration: Option<u64>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhaleTrack {
+    id: u64,
+    title: String,
+    artist: Option<FunkwhaleArtist>,
+    #[serde(default)]
+    artist_credit: Vec<FunkwhaleCredit>,
+    album: Option<FunkwhaleAlbum>,
+    #[serde(default)]
+    uploads: Vec<FunkwhaleUpload>,
+    mbid: Option<String>,
+}
+
+impl From<FunkwhaleTrack> for Candidate {
+    fn from(fw_track: FunkwhaleTrack) -> Candidate {
+        let artist = fw_track.artist.map(|a| a.name)
+            .or(fw_track.artist_credit.into_iter().next().map(|c| c.artist.name))
+            .unwrap_or_default();
+        let year = fw_track.album.as_ref()
+            .and_then(|a| a.release_date.as_ref())
+            .and_then(|date| date.get(..4))
+            .and_then(|y| y.parse().ok());
+
+        Candidate {
+            id: fw_track.id.to_string(),
+            title: fw_track.title,
+            artist,
+            album: fw_track.album.map(|a| a.title),
+            duration: fw_track.uploads.first().and_then(|u| u.duration),
+            year,
+            recording_mbid: fw_track.mbid.filter(|mbid| !mbid.is_empty()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Page<T> {
+    results: Vec<T>,
+    /// URL of the next page, if any
+    next: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct FunkwhalePlaylist {
+    id: u64,
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PlaylistTrack {
+    track: FunkwhaleTrack,
+}
+
+pub struct FunkwhaleClient {
+    root: String,
+    token: String,
+    client: reqwest::blocking::Client,
+}
+
+impl FunkwhaleClient {
+    /// Connect using `FUNKWHALE_HOST` and an application token with the
+    /// read:libraries and write:playlists scopes in `FUNKWHALE_TOKEN`.
+    pub fn from_env() -> Result<FunkwhaleClient> {
+        let root = std::env::var("FUNKWHALE_HOST").context("FUNKWHALE_HOST not set")?;
+        let token = std::env::var("FUNKWHALE_TOKEN").context("FUNKWHALE_TOKEN not set")?;
+
+        Ok(FunkwhaleClient {
+            root: format!("{}/api/v1", root.trim_end_matches('/')),
+            token,
+            client: reqwest::blocking::Client::new(),
+        })
+    }
+
+    fn get(&self, api: &str) -> RequestBuilder {
+        self.client.get(format!("{}{api}", self.root)).bearer_auth(&self.token)
+    }
+
+    fn post(&self, api: &str) -> RequestBuilder {
+        self.client.post(format!("{}{api}", self.root)).bearer_auth(&self.token)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+        let res = request.send().context("Failed to send Funkwhale request")?;
+        let status = res.status();
+
+        if !status.is_success() {
+            let body = res.text().unwrap_or_default();
+            return Err(anyhow!("Funkwhale request failed: {} - {}", status, body));
+        }
+        Ok(res)
+    }
+
+    // Results from all pages of a paginated API
+    fn all_pages<T: serde::de::DeserializeOwned>(&self, api: &str) -> Result<Vec<T>> {
+        let mut page: Page<T> = self.send(self.get(api))?.json()?;
+        let mut results = page.results;
+
+        while let Some(next) = page.next {
+            page = self.send(self.client.get(next).bearer_auth(&self.token))?.json()?;
+            results.extend(page.results);
+        }
+        Ok(results)
+    }
+
+    fn tracks(&self, params: &[(&str, &str)]) -> Result<Vec<Candidate>> {
+        let request = self.get("/tracks/").query(&[("playable", "true"), ("page_size", "10")]).query(params);
+        let tracks: Page<FunkwhaleTrack> = self.send(request)?.json()?;
+        Ok(tracks.results.into_iter().map(Candidate::from).collect())
+    }
+
+    fn find_playlist(&self, name: &str) -> Result<Option<u64>> {
+        let request = self.get("/playlists/").query(&[("name", name), ("scope", "me")]);
+        let playlists: Page<FunkwhalePlaylist> = self.send(request)?.json()?;
+        Ok(playlists.results.into_iter().find(|p| p.name == name).map(|p| p.id))
+    }
+}
+
+impl Target for FunkwhaleClient {
+    fn override_platform(&self) -> &'static str {
+        "funkwhale"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("funkwhale:{}", self.root)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        self.tracks(&[("q", query)])
+    }
+
+    /// Filter by recording MBID first, then search by title and leave the
+    /// artist to the matcher.
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(mbid) = track.recording_mbid() {
+            let tagged = self.tracks(&[("mbid", &mbid)])?;
+            if !tagged.is_empty() {
+                return Ok(tagged);
+            }
+        }
+
+        self.search(&track.title)
+    }
+
+    /// Add the tracks to an existing playlist with the same name, else create
+    /// a new one.
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        let (playlist_id, existing) = match self.find_playlist(name)? {
+            Some(playlist_id) => {
+                let tracks: Vec<PlaylistTrack> = self.all_pages(&format!("/playlists/{playlist_id}/tracks/"))?;
+                (playlist_id, tracks.into_iter().map(|t| t.track.id).collect::<Vec<u64>>())
+            },
+            None => {
+                let request = self.post("/playlists/").json(&serde_json::json!({ "name": name, "privacy_level": "me" }));
+                let playlist: FunkwhalePlaylist = self.send(request)?.json()?;
+                (playlist.id, vec![])
+            },
+        };
+
+        let new_ids = ids.iter()
+            .map(|id| id.parse::<u64>().map_err(|_| anyhow!("Invalid Funkwhale track id {id}")))
+            .collect::<Result<Vec<u64>>>()?
+            .into_iter()
+            .filter(|id| !existing.contains(id))
+            .collect::<Vec<u64>>();
+
+        if !new_ids.is_empty() {
+            let request = self.post(&format!("/playlists/{playlist_id}/add/"))
+                .json(&serde_json::json!({ "tracks": new_ids, "allow_duplicates": false }));
+            self.send(request)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_tracks() {
+        let page: Page<FunkwhaleTrack> = serde_json::from_str(r#"{"count": 3, "next": "https://fw.example.com/api/v1/tracks/?page=2", "results": [
+            {"id": 1, "title": "Get Lucky", "artist": {"name": "Daft Punk"}, "mbid": "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae",
+             "album": {"title": "Random Access Memories", "release_date": "2013-05-17"}, "uploads": [{"duration": 369}]},
+            {"id": 2, "title": "Halo", "artist_credit": [{"artist": {"name": "Beyoncé"}}, {"artist": {"name": "Someone"}}], "mbid": ""},
+            {"id": 3, "title": "Untagged", "album": {"title": "Singles", "release_date": null}, "mbid": null}
+        ]}"#).unwrap();
+        assert!(page.next.is_some());
+        let candidates = page.results.into_iter().map(Candidate::from).collect::<Vec<Candidate>>();
+
+        assert_eq!(candidates[0].id, "1");
+        assert_eq!(candidates[0].artist, "Daft Punk");
+        assert_eq!(candidates[0].album.as_deref(), Some("Random Access Memories"));
+        assert_eq!(candidates[0].year, Some(2013));
+        assert_eq!(candidates[0].duration, Some(369));
+        assert_eq!(candidates[0].recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+
+        assert_eq!(candidates[1].artist, "Beyoncé");
+        assert_eq!((candidates[1].duration, &candidates[1].recording_mbid), (None, &None));
+
+        assert_eq!(candidates[2].artist, "");
+        assert_eq!((candidates[2].year, &candidates[2].recording_mbid), (None, &None));
+    }
+}
@@ -0,0 +1,49 @@
+use reqwest::blocking::RequestBuilder;
+
+use super::media_browser::{self, MediaBrowserClient, Server};
+
+pub struct Jellyfin;
+
+pub type JellyfinClient = MediaBrowserClient<Jellyfin>;
+
+impl Server for Jellyfin {
+    const NAME: &'static str = "Jellyfin";
+    const PLATFORM: &'static str = "jellyfin";
+    const ENV_PREFIX: &'static str = "JF";
+    const ROUTE_PREFIX: &'static str = "";
+
+    // The token goes into the same header as the client description
+    fn authorize(request: RequestBuilder, token: &str) -> RequestBuilder {
+        let mut header = media_browser::client_description();
+        if !token.is_empty() {
+            header.push_str(&format!(", Token=\"{token}\""));
+        }
+        request.header("Authorization", header)
+    }
+
+    fn login_body(request: RequestBuilder, user: &str, password: &str) -> RequestBuilder {
+        request.json(&serde_json::json!({ "Username": user, "Pw": password }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorizes_requests() {
+        let client = reqwest::blocking::Client::new();
+
+        let request = Jellyfin::authorize(client.get("http://jf/Users"), "").build().unwrap();
+        let header = request.headers()["Authorization"].to_str().unwrap();
+        assert!(header.starts_with("MediaBrowser Client=\"mbzlists-resolvers\""), "{header}");
+        assert!(!header.contains("Token="), "{header}");
+
+        let request = Jellyfin::authorize(client.get("http://jf/Users"), "abc").build().unwrap();
+        assert!(request.headers()["Authorization"].to_str().unwrap().ends_with(", Token=\"abc\""));
+
+        let request = Jellyfin::login_body(client.post("http://jf/Users/AuthenticateByName"), "me", "p\"ss").build().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(request.body().and_then(|body| body.as_bytes()).unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "Username": "me", "Pw": "p\"ss" }));
+    }
+}
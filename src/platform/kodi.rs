@@ -0,0 +1,357 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::{escape_xml, Track};
+use crate::platform::local::{file_location, FileIndex, M3u8Output};
+
+const SONG_PROPERTIES: [&str; 7] = ["title", "artist", "album", "duration", "year", "musicbrainztrackid", "file"];
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum KodiPlaylistFormat {
+    /// Static playlist listing the files in order
+    M3u,
+    /// Smart playlist matching the file names, Kodi decides the order. Falls
+    /// back to M3U when other songs share a file name with the playlist's.
+    Xsp,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct KodiSong {
+    file: String,
+    title: String,
+    #[serde(default)]
+    artist: Vec<String>,
+    album: Option<String>,
+    duration: Option<u64>,
+    year: Option<u32>,
+    musicbrainztrackid: Option<String>,
+}
+
+impl From<KodiSong> for Candidate {
+    fn from(song: KodiSong) -> Candidate {
+        Candidate {
+            id: song.file,
+            title: song.title,
+            artist: song.artist.into_iter().next().unwrap_or_default(),
+            album: song.album.filter(|a| !a.is_empty()),
+            duration: song.duration,
+            year: song.year.filter(|y| *y != 0),
+            recording_mbid: song.musicbrainztrackid.filter(|mbid| !mbid.is_empty()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SongsResult {
+    #[serde(default)]
+    songs: Vec<KodiSong>,
+}
+
+enum Transport {
+    Http { url: String, auth: Option<(String, String)>, client: reqwest::blocking::Client },
+    /// Raw JSON objects over TCP, port 9090 by default
+    Tcp(RefCell<TcpStream>),
+}
+
+pub struct KodiClient {
+    host: String,
+    transport: Transport,
+    next_id: Cell<u64>,
+    playlist_dir: PathBuf,
+    format: KodiPlaylistFormat,
+    // Songs seen while resolving, for the playlist metadata
+    seen: RefCell<HashMap<String, Candidate>>,
+}
+
+impl KodiClient {
+    /// Connect using `KODI_HOST`, either http://host:8080 (with optional
+    /// `KODI_USER` and `KODI_PASS`) or tcp://host:9090. Playlists are written
+    /// to the music playlists directory of the Kodi profile at `profile`,
+    /// `KODI_PROFILE` or ~/.kodi/userdata.
+    pub fn from_env(profile: Option<PathBuf>, format: KodiPlaylistFormat) -> Result<KodiClient> {
+        let host = std::env::var("KODI_HOST").unwrap_or("http://localhost:8080".to_string());
+
+        let transport = match host.strip_prefix("tcp://") {
+            Some(address) => {
+                let stream = TcpStream::connect(address).with_context(|| format!("Unable to connect to Kodi at {address}"))?;
+                Transport::Tcp(RefCell::new(stream))
+            },
+            None => Transport::Http {
+                url: format!("{}/jsonrpc", host.trim_end_matches('/')),
+                auth: std::env::var("KODI_USER").ok().map(|user| (user, std::env::var("KODI_PASS").unwrap_or_default())),
+                client: reqwest::blocking::Client::new(),
+            },
+        };
+
+        let profile = profile
+            .or(std::env::var("KODI_PROFILE").ok().map(PathBuf::from))
+            .or(dirs::home_dir().map(|home| home.join(".kodi").join("userdata")))
+            .ok_or_else(|| anyhow!("Unable to find the Kodi profile, pass --profile"))?;
+
+        let kodi_client = KodiClient {
+            host,
+            transport,
+            next_id: Cell::new(1),
+            playlist_dir: profile.join("playlists").join("music"),
+            format,
+            seen: RefCell::new(HashMap::new()),
+        };
+
+        kodi_client.call("JSONRPC.Ping", json!({})).context("Unable to reach Kodi")?;
+        Ok(kodi_client)
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id });
+
+        let response: Value = match &self.transport {
+            Transport::Http { url, auth, client } => {
+                let mut builder = client.post(url).json(&request);
+                if let Some((user, password)) = auth {
+                    builder = builder.basic_auth(user, Some(password));
+                }
+
+                let res = builder.send().context("Failed to send Kodi request")?;
+                let status = res.status();
+                if !status.is_success() {
+                    let body = res.text().unwrap_or_default();
+                    return Err(anyhow!("Kodi request failed: {} - {}", status, body));
+                }
+                res.json().context("Failed to parse Kodi response")?
+            },
+            Transport::Tcp(stream) => {
+                let mut stream = stream.borrow_mut();
+                serde_json::to_writer(&mut *stream, &request)?;
+
+                // Notifications can arrive in between, skip till our response
+                let mut values = serde_json::Deserializer::from_reader(&mut *stream).into_iter::<Value>();
+                loop {
+                    let value = values.next().ok_or_else(|| anyhow!("Kodi closed the connection"))??;
+                    if value.get("id") == Some(&json!(id)) {
+                        break value;
+                    }
+                }
+            },
+        };
+
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("Kodi error: {}", error));
+        }
+        response.get("result").cloned().ok_or_else(|| anyhow!("Missing result in Kodi response: {}", response))
+    }
+
+    fn songs(&self, filter: Value) -> Result<Vec<Candidate>> {
+        let result = self.call("AudioLibrary.GetSongs", json!({
+            "properties": SONG_PROPERTIES,
+            "filter": filter,
+            "limits": { "end": 10 },
+        }))?;
+        let songs: SongsResult = serde_json::from_value(result).context("Unexpected AudioLibrary.GetSongs response")?;
+
+        let candidates = songs.songs.into_iter().map(Candidate::from).collect::<Vec<Candidate>>();
+        self.seen.borrow_mut().extend(candidates.iter().map(|c| (c.id.clone(), c.clone())));
+        Ok(candidates)
+    }
+
+    // Smart playlist rules can only be or-ed, so a file name rule pulls in
+    // all songs with that name, whatever their directory
+    fn file_name_collides(&self, file: &str) -> Result<bool> {
+        let songs = self.songs(json!({ "field": "filename", "operator": "is", "value": file_name(file) }))?;
+        Ok(songs.iter().any(|song| song.id != file))
+    }
+
+    fn write_m3u(&self, name: &str, files: &[String]) -> Result<()> {
+        let output = M3u8Output {
+            path: Some(self.playlist_dir.join(format!("{}.m3u", name.replace('/', "_")))),
+            absolute: true,
+        };
+        let index = FileIndex::new(self.seen.borrow().values().cloned().collect());
+        output.write(name, files, &index)
+    }
+
+    fn write_xsp(&self, name: &str, files: &[String]) -> Result<PathBuf> {
+        let mut xsp = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n");
+        xsp.push_str("<smartplaylist type=\"songs\">\n");
+        xsp.push_str(&format!("    <name>{}</name>\n", escape_xml(name)));
+        xsp.push_str("    <match>one</match>\n");
+        for file in files {
+            xsp.push_str(&format!("    <rule field=\"filename\" operator=\"is\">\n        <value>{}</value>\n    </rule>\n", escape_xml(&file_name(file))));
+        }
+        xsp.push_str("</smartplaylist>\n");
+
+        let path = self.playlist_dir.join(format!("{}.xsp", name.replace('/', "_")));
+        std::fs::write(&path, xsp).with_context(|| format!("Unable to write playlist to {:?}", path))?;
+        Ok(path)
+    }
+}
+
+fn file_name(file: &str) -> String {
+    Path::new(file).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or(file.to_string())
+}
+
+impl Target for KodiClient {
+    fn override_platform(&self) -> &'static str {
+        "kodi"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("kodi:{}", self.host)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        self.songs(json!({ "or": [
+            { "field": "title", "operator": "contains", "value": query },
+            { "field": "artist", "operator": "contains", "value": query },
+            { "field": "album", "operator": "contains", "value": query },
+        ]}))
+    }
+
+    /// Filter on the MusicBrainz track id first, then on the title and leave
+    /// the artist to the matcher.
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(mbid) = track.recording_mbid() {
+            // Older Kodi versions don't know this filter field
+            match self.songs(json!({ "field": "musicbrainztrackid", "operator": "is", "value": mbid })) {
+                Ok(songs) if !songs.is_empty() => return Ok(songs),
+                Ok(_) => {},
+                Err(err) => debug!("MBID filter failed: {err}"),
+            }
+        }
+
+        self.songs(json!({ "field": "title", "operator": "contains", "value": track.title }))
+    }
+
+    fn location(&self, id: &str) -> Option<String> {
+        file_location(id)
+    }
+
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        std::fs::create_dir_all(&self.playlist_dir).with_context(|| format!("Unable to create {:?}", self.playlist_dir))?;
+
+        match self.format {
+            KodiPlaylistFormat::M3u => self.write_m3u(name, &ids),
+            KodiPlaylistFormat::Xsp => {
+                for file in &ids {
+                    if self.file_name_collides(file)? {
+                        warn!("Other songs are named like {:?}, writing an M3U playlist instead", file_name(file));
+                        return self.write_m3u(name, &ids);
+                    }
+                }
+
+                let path = self.write_xsp(name, &ids)?;
+                info!("Wrote {:?}", path);
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    // Kodi on the TCP transport, answering requests with `respond`. An object
+    // with an `error` key is sent as an error, anything else as the result.
+    fn fake_kodi(respond: fn(&Value) -> Value) -> Transport {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for request in serde_json::Deserializer::from_reader(stream).into_iter::<Value>() {
+                let Ok(request) = request else { break };
+                let response = match respond(&request) {
+                    error if error.get("error").is_some() => json!({ "jsonrpc": "2.0", "id": request["id"], "error": error["error"] }),
+                    result => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                };
+                // A notification first, which the client has to skip
+                writer.write_all(br#"{"jsonrpc": "2.0", "method": "AudioLibrary.OnScanStarted", "params": {}}"#).unwrap();
+                serde_json::to_writer(&mut writer, &response).unwrap();
+            }
+        });
+
+        Transport::Tcp(RefCell::new(TcpStream::connect(address).unwrap()))
+    }
+
+    fn client(name: &str, format: KodiPlaylistFormat, respond: fn(&Value) -> Value) -> KodiClient {
+        KodiClient {
+            host: "tcp://kodi".to_string(),
+            transport: fake_kodi(respond),
+            next_id: Cell::new(1),
+            playlist_dir: std::env::temp_dir().join(format!("mbzlists-kodi-{name}-{}", std::process::id())),
+            format,
+            seen: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn song(file: &str, title: &str) -> Value {
+        json!({ "songid": 1, "label": title, "file": file, "title": title, "artist": ["Daft Punk"], "album": "", "duration": 369, "year": 0 })
+    }
+
+    #[test]
+    fn writes_smart_playlists() {
+        let kodi = client("xsp", KodiPlaylistFormat::Xsp, |request| {
+            let filter = &request["params"]["filter"];
+            json!({ "songs": [song(&format!("/music/{}", filter["value"].as_str().unwrap()), "x")] })
+        });
+        kodi.create_playlist("Rock & Roll <Mix>", vec!["/music/Rock & Roll.flac".to_string(), "/music/b.mp3".to_string()]).unwrap();
+
+        let xsp = std::fs::read_to_string(kodi.playlist_dir.join("Rock & Roll <Mix>.xsp")).unwrap();
+        assert_eq!(xsp, "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n\
+                         <smartplaylist type=\"songs\">\n    \
+                         <name>Rock &amp; Roll &lt;Mix&gt;</name>\n    \
+                         <match>one</match>\n    \
+                         <rule field=\"filename\" operator=\"is\">\n        <value>Rock &amp; Roll.flac</value>\n    </rule>\n    \
+                         <rule field=\"filename\" operator=\"is\">\n        <value>b.mp3</value>\n    </rule>\n\
+                         </smartplaylist>\n");
+
+        std::fs::remove_dir_all(&kodi.playlist_dir).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_m3u() {
+        let kodi = client("m3u", KodiPlaylistFormat::Xsp, |request| {
+            let name = request["params"]["filter"]["value"].as_str().unwrap();
+            json!({ "songs": [song(&format!("/music/a/{name}"), "Intro"), song(&format!("/music/b/{name}"), "Intro")] })
+        });
+        kodi.create_playlist("Mix", vec!["/music/a/01 Intro.flac".to_string()]).unwrap();
+
+        assert!(!kodi.playlist_dir.join("Mix.xsp").exists());
+        let m3u = std::fs::read_to_string(kodi.playlist_dir.join("Mix.m3u")).unwrap();
+        assert_eq!(m3u, "#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:369,Daft Punk - Intro\n/music/a/01 Intro.flac\n");
+
+        std::fs::remove_dir_all(&kodi.playlist_dir).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_title_filter() {
+        let kodi = client("filter", KodiPlaylistFormat::M3u, |request| {
+            match request["params"]["filter"]["field"].as_str() {
+                Some("musicbrainztrackid") => json!({ "error": { "code": -32602, "message": "Invalid params." } }),
+                _ => json!({ "songs": [song("/music/08 Get Lucky.flac", "Get Lucky")] }),
+            }
+        });
+        let track = Track {
+            title: "Get Lucky".to_string(),
+            creator: "Daft Punk".to_string(),
+            identifiers: vec!["https://musicbrainz.org/recording/8f3471b5-7e6a-48da-86a9-c1c07a0f47ae".to_string()],
+            ..Default::default()
+        };
+
+        let candidates = kodi.candidates(&track).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, "/music/08 Get Lucky.flac");
+        assert_eq!((&candidates[0].album, candidates[0].year), (&None, None));
+    }
+}
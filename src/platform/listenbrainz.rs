@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde_json::json;
+
+use crate::mbzlists::{Playlist, JSPF_PLAYLIST_EXTENSION, JSPF_TRACK_EXTENSION};
+
+#[derive(serde::Serialize, Debug)]
+pub struct JspfTrack {
+    pub title: String,
+    pub creator: String,
+    pub identifier: Vec<String>,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extension: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct JspfPlaylist {
+    pub title: String,
+    pub track: Vec<JspfTrack>,
+    pub extension: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Jspf {
+    pub playlist: JspfPlaylist,
+}
+
+impl Jspf {
+    /// Convert a playlist to JSPF as accepted by ListenBrainz. Tracks are
+    /// identified by recording MBID there, so tracks without one are left out.
+    pub fn from_playlist(playlist: &Playlist, name: &str, public: bool) -> Jspf {
+        let mut tracks = vec![];
+
+        for track in &playlist.tracklist.tracks {
+            let Some(mbid) = track.recording_mbid() else {
+                warn!("Skipping {} - {} without a recording MBID", track.creator, track.title);
+                continue;
+            };
+
+            // Extensions read from JSPF are passed on as they were
+            let mut extension = track.extensions.clone();
+            let artist_identifiers = track.artist_mbids().into_iter()
+                .map(|mbid| format!("https://musicbrainz.org/artist/{mbid}"))
+                .collect::<Vec<String>>();
+            if !artist_identifiers.is_empty() {
+                let mb_extension = extension.entry(JSPF_TRACK_EXTENSION).or_insert_with(|| json!({}));
+                if let Some(mb_extension) = mb_extension.as_object_mut() {
+                    mb_extension.insert("artist_identifiers".to_string(), json!(artist_identifiers));
+                }
+            }
+
+            tracks.push(JspfTrack {
+                title: track.title.clone(),
+                creator: track.creator.clone(),
+                identifier: vec![format!("https://musicbrainz.org/recording/{mbid}")],
+                extension,
+            });
+        }
+
+        let mut extension = serde_json::Map::new();
+        extension.insert(JSPF_PLAYLIST_EXTENSION.to_string(), json!({ "public": public }));
+
+        Jspf {
+            playlist: JspfPlaylist { title: name.to_string(), track: tracks, extension },
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct CreateResponse {
+    playlist_mbid: String,
+}
+
+pub struct ListenBrainzClient {
+    root: String,
+    token: String,
+    client: reqwest::blocking::Client,
+}
+
+impl ListenBrainzClient {
+    /// Connect using the user token in `LB_TOKEN`. `LB_HOST` can point to a
+    /// self-hosted server, defaults to https://api.listenbrainz.org.
+    pub fn from_env() -> Result<ListenBrainzClient> {
+        let root = std::env::var("LB_HOST").unwrap_or("https://api.listenbrainz.org".to_string());
+        let token = std::env::var("LB_TOKEN").context("LB_TOKEN not set")?;
+        Ok(Self::new(&root, token))
+    }
+
+    pub fn new(root: &str, token: String) -> ListenBrainzClient {
+        ListenBrainzClient {
+            root: root.trim_end_matches('/').to_string(),
+            token,
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    /// Create the playlist and return its MBID
+    pub fn create_playlist(&self, jspf: &Jspf) -> Result<String> {
+        let res = self.client.post(format!("{}/1/playlist/create", self.root))
+            .header("Authorization", format!("Token {}", self.token))
+            .json(jspf)
+            .send()
+            .context("Failed to send ListenBrainz request")?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().unwrap_or_default();
+            return Err(anyhow!("ListenBrainz request failed: {} - {}", status, body));
+        }
+
+        let created: CreateResponse = res.json().context("Failed to parse ListenBrainz response")?;
+        Ok(created.playlist_mbid)
+    }
+
+    /// Web page of a playlist. The API of the public server has a host of its
+    /// own while self-hosted servers serve both from the same one.
+    pub fn playlist_url(&self, mbid: &str) -> String {
+        format!("{}/playlist/{mbid}", self.root.replacen("://api.", "://", 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbzlists::Track;
+
+    #[test]
+    fn converts_playlists() {
+        let mut extensions = serde_json::Map::new();
+        extensions.insert(JSPF_TRACK_EXTENSION.to_string(), json!({"added_by": "someone"}));
+        extensions.insert("https://example.com/ext".to_string(), json!({"rating": 5}));
+
+        let playlist = Playlist {
+            tracklist: crate::mbzlists::Tracklist { tracks: vec![
+                Track {
+                    title: "Get Lucky".to_string(),
+                    creator: "Daft Punk feat. Pharrell Williams".to_string(),
+                    identifiers: vec![
+                        "https://musicbrainz.org/recording/8f3471b5-7e6a-48da-86a9-c1c07a0f47ae".to_string(),
+                        "https://musicbrainz.org/artist/056e4f3e-d505-4dad-8ec1-d04f521cbb56".to_string(),
+                    ],
+                    extensions,
+                    ..Default::default()
+                },
+                Track { title: "No MBID".to_string(), creator: "Someone".to_string(), ..Default::default() },
+                Track {
+                    title: "Halo".to_string(),
+                    creator: "Beyoncé".to_string(),
+                    identifiers: vec!["https://musicbrainz.org/recording/0383dadf-2a4e-4d10-a46a-e9e041da8eb3".to_string()],
+                    ..Default::default()
+                },
+            ] },
+            ..Default::default()
+        };
+
+        let jspf = serde_json::to_value(Jspf::from_playlist(&playlist, "Mix", true)).unwrap();
+        assert_eq!(jspf, json!({"playlist": {
+            "title": "Mix",
+            "extension": {JSPF_PLAYLIST_EXTENSION: {"public": true}},
+            "track": [
+                {
+                    "title": "Get Lucky",
+                    "creator": "Daft Punk feat. Pharrell Williams",
+                    "identifier": ["https://musicbrainz.org/recording/8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"],
+                    "extension": {
+                        JSPF_TRACK_EXTENSION: {
+                            "added_by": "someone",
+                            "artist_identifiers": ["https://musicbrainz.org/artist/056e4f3e-d505-4dad-8ec1-d04f521cbb56"],
+                        },
+                        "https://example.com/ext": {"rating": 5},
+                    },
+                },
+                {
+                    "title": "Halo",
+                    "creator": "Beyoncé",
+                    "identifier": ["https://musicbrainz.org/recording/0383dadf-2a4e-4d10-a46a-e9e041da8eb3"],
+                },
+            ],
+        }}));
+    }
+
+    #[test]
+    fn links_playlists() {
+        let mbid = "6bb4fa57-3dd8-4e2c-9a61-7b4b2a4e4e55";
+        assert_eq!(ListenBrainzClient::new("https://api.listenbrainz.org", String::new()).playlist_url(mbid),
+                   format!("https://listenbrainz.org/playlist/{mbid}"));
+        assert_eq!(ListenBrainzClient::new("http://localhost:8100/", String::new()).playlist_url(mbid),
+                   format!("http://localhost:8100/playlist/{mbid}"));
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Context, Result};
+use lofty::prelude::*;
+use log::{debug, info, warn};
+use std::path::{Component, Path, PathBuf};
+
+use crate::import::{Candidate, Target};
+use crate::matching;
+use crate::mbzlists::Track;
+
+/// Audio files with their tags, searched in memory. Candidate ids are the
+/// file paths.
+pub struct FileIndex {
+    files: Vec<Candidate>,
+}
+
+impl FileIndex {
+    pub fn new(files: Vec<Candidate>) -> FileIndex {
+        FileIndex { files }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Candidate> {
+        self.files.iter().find(|c| c.id == id)
+    }
+
+    /// Files with all the query words in their artist, title or album
+    pub fn search(&self, query: &str) -> Vec<Candidate> {
+        let words = matching::normalize(query).split(' ').map(str::to_string).collect::<Vec<String>>();
+
+        self.files.iter()
+            .filter(|c| {
+                let text = matching::normalize(&format!("{} {} {}", c.artist, c.title, c.album.as_deref().unwrap_or("")));
+                words.iter().all(|word| text.split(' ').any(|w| w == word))
+            })
+            .take(10)
+            .cloned()
+            .collect()
+    }
+
+    /// Files tagged with the track's recording MBID, else the ones with a
+    /// matching title. Artists are left to the matcher.
+    pub fn candidates(&self, track: &Track) -> Vec<Candidate> {
+        if let Some(mbid) = track.recording_mbid() {
+            let tagged = self.files.iter()
+                .filter(|c| c.recording_mbid.as_ref() == Some(&mbid))
+                .cloned()
+                .collect::<Vec<Candidate>>();
+            if !tagged.is_empty() {
+                return tagged;
+            }
+        }
+
+        self.files.iter().filter(|c| matching::title_matches(&c.title, &track.title)).cloned().collect()
+    }
+}
+
+/// Where and how to write M3U8 playlists. Playlists go to `path`, defaulting
+/// to `<name>.m3u8` in the current directory, with file paths relative to the
+/// playlist unless `absolute` is set.
+pub struct M3u8Output {
+    pub path: Option<PathBuf>,
+    pub absolute: bool,
+}
+
+impl M3u8Output {
+    pub fn write(&self, name: &str, paths: &[String], index: &FileIndex) -> Result<()> {
+        let output = self.path.clone().unwrap_or(PathBuf::from(format!("{}.m3u8", name.replace('/', "_"))));
+        let output_dir = std::path::absolute(&output)?.parent().map(Path::to_path_buf).unwrap_or_default();
+        // Library paths are canonical, so resolve symlinks here as well
+        let output_dir = output_dir.canonicalize().unwrap_or(output_dir);
+
+        let mut m3u = String::from("#EXTM3U\n");
+        m3u.push_str(&format!("#PLAYLIST:{name}\n"));
+
+        for id in paths {
+            if let Some(candidate) = index.get(id) {
+                let duration = candidate.duration.map(|d| d as i64).unwrap_or(-1);
+                m3u.push_str(&format!("#EXTINF:{duration},{} - {}\n", candidate.artist, candidate.title));
+            }
+
+            let path = PathBuf::from(id);
+            let path = if self.absolute { path } else { relative_to(&path, &output_dir) };
+            m3u.push_str(&format!("{}\n", path.display()));
+        }
+
+        std::fs::write(&output, m3u).with_context(|| format!("Unable to write playlist to {:?}", output))?;
+        info!("Wrote {:?}", output);
+        Ok(())
+    }
+}
+
+/// File URL for a library path. Paths that are URLs already (like Kodi's
+/// network sources) are kept as they are.
+pub fn file_location(path: &str) -> Option<String> {
+    if path.contains("://") {
+        return Some(path.to_string());
+    }
+    url::Url::from_file_path(path).ok().map(String::from)
+}
+
+/// Tagged audio files under a directory, written out as M3U8 playlists
+pub struct LocalLibrary {
+    root: PathBuf,
+    index: FileIndex,
+    output: M3u8Output,
+}
+
+impl LocalLibrary {
+    /// Index all files under `root` with tags lofty can read (ID3v2, Vorbis
+    /// comments, MP4 atoms and others).
+    pub fn scan(root: PathBuf, output: M3u8Output) -> Result<LocalLibrary> {
+        let root = root.canonicalize().with_context(|| format!("Unable to open music directory {:?}", root))?;
+        let mut files = vec![];
+        scan_dir(&root, &mut files)?;
+        info!("Indexed {} files in {:?}", files.len(), root);
+
+        Ok(LocalLibrary { root, index: FileIndex::new(files), output })
+    }
+}
+
+fn scan_dir(dir: &Path, index: &mut Vec<Candidate>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Unable to read {:?}", dir))? {
+        // One unreadable entry should not stop indexing the whole library
+        let (path, file_type) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Skipping an entry in {:?}: {err}", dir);
+                continue;
+            },
+        };
+
+        // Symlinked directories are not followed since they can form loops
+        if file_type.is_dir() {
+            if let Err(err) = scan_dir(&path, index) {
+                warn!("Skipping {:?}: {err:#}", path);
+            }
+        } else if path.extension().and_then(lofty::file::FileType::from_ext).is_some() {
+            match read_tags(&path) {
+                Ok(candidate) => index.push(candidate),
+                Err(err) => debug!("Skipping {:?}: {err}", path),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_tags(path: &Path) -> Result<Candidate> {
+    let file = lofty::read_from_path(path)?;
+    let tag = file.primary_tag().or(file.first_tag()).ok_or_else(|| anyhow!("No tags"))?;
+
+    Ok(Candidate {
+        id: path.to_string_lossy().to_string(),
+        title: tag.title().ok_or_else(|| anyhow!("No title tag"))?.to_string(),
+        artist: tag.artist().map(|a| a.to_string()).unwrap_or_default(),
+        album: tag.album().map(|a| a.to_string()),
+        duration: Some(file.properties().duration().as_secs()),
+        year: tag.date().map(|d| d.year as u32),
+        recording_mbid: tag.get_string(ItemKey::MusicBrainzRecordingId).map(str::to_string),
+    })
+}
+
+// Path of `path` as seen from the directory `base`, both absolute
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path_components = path.components().collect::<Vec<Component>>();
+    let base_components = base.components().collect::<Vec<Component>>();
+    let common = path_components.iter().zip(&base_components).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    relative.extend(&path_components[common..]);
+    relative
+}
+
+impl Target for LocalLibrary {
+    fn override_platform(&self) -> &'static str {
+        "local"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("local:{}", self.root.display())
+    }
+
+    fn offline(&self) -> bool {
+        true
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        Ok(self.index.search(query))
+    }
+
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        Ok(self.index.candidates(track))
+    }
+
+    fn location(&self, id: &str) -> Option<String> {
+        file_location(id)
+    }
+
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        self.output.write(name, &ids, &self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, artist: &str, title: &str, album: Option<&str>) -> Candidate {
+        Candidate {
+            id: path.to_string(),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn relates_paths() {
+        let path = Path::new("/music/Daft Punk/RAM/08 Get Lucky.flac");
+
+        assert_eq!(relative_to(path, Path::new("/music")), PathBuf::from("Daft Punk/RAM/08 Get Lucky.flac"));
+        assert_eq!(relative_to(path, Path::new("/music/Daft Punk/RAM")), PathBuf::from("08 Get Lucky.flac"));
+        assert_eq!(relative_to(path, Path::new("/music/playlists")), PathBuf::from("../Daft Punk/RAM/08 Get Lucky.flac"));
+        assert_eq!(relative_to(path, Path::new("/home/me/lists")), PathBuf::from("../../../music/Daft Punk/RAM/08 Get Lucky.flac"));
+        assert_eq!(relative_to(path, Path::new("/")), PathBuf::from("music/Daft Punk/RAM/08 Get Lucky.flac"));
+    }
+
+    #[test]
+    fn searches_files() {
+        let index = FileIndex::new(vec![
+            file("/music/1.flac", "Daft Punk", "Get Lucky", Some("Random Access Memories")),
+            file("/music/2.flac", "Daft Punk", "Lose Yourself to Dance", Some("Random Access Memories")),
+            file("/music/3.flac", "Beyoncé", "Halo", None),
+            file("/music/4.flac", "Luckyman", "Song", None),
+        ]);
+        let ids = |query: &str| index.search(query).into_iter().map(|c| c.id).collect::<Vec<String>>();
+
+        assert_eq!(ids("daft punk"), vec!["/music/1.flac", "/music/2.flac"]);
+        assert_eq!(ids("Get Lucky Daft Punk"), vec!["/music/1.flac"]);
+        assert_eq!(ids("random access dance"), vec!["/music/2.flac"]);
+        assert_eq!(ids("beyonce halo"), vec!["/music/3.flac"]);
+        // Whole words only
+        assert_eq!(ids("lucky"), vec!["/music/1.flac"]);
+        assert!(ids("daft punk halo").is_empty());
+    }
+}
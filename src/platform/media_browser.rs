@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::RequestBuilder;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+
+const CLIENT_NAME: &str = "mbzlists-resolvers";
+
+/// A server speaking the API Jellyfin and Emby inherited from Media Browser.
+/// They mostly differ in how requests are authenticated.
+pub trait Server {
+    /// Name used in messages
+    const NAME: &'static str;
+    /// Platform name used in overrides and the resolution cache
+    const PLATFORM: &'static str;
+    /// Prefix of the environment variables the client is configured with
+    const ENV_PREFIX: &'static str;
+    /// Path all API routes live under
+    const ROUTE_PREFIX: &'static str;
+
+    /// Add authentication to a request, `token` is empty before login
+    fn authorize(request: RequestBuilder, token: &str) -> RequestBuilder;
+
+    /// Add the credentials to a login request
+    fn login_body(request: RequestBuilder, user: &str, password: &str) -> RequestBuilder;
+}
+
+/// Client description both servers expect in `MediaBrowser` auth headers
+pub fn client_description() -> String {
+    format!(
+        "MediaBrowser Client=\"{CLIENT_NAME}\", Device=\"{CLIENT_NAME}\", DeviceId=\"{CLIENT_NAME}\", Version=\"{}\"",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Item {
+    id: String,
+    name: String,
+    #[serde(default)]
+    artists: Vec<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    /// Duration in ticks of 100 nanoseconds
+    run_time_ticks: Option<u64>,
+    production_year: Option<u32>,
+    #[serde(default)]
+    provider_ids: HashMap<String, String>,
+}
+
+impl Item {
+    // Provider id keys are not consistently cased across server versions
+    fn provider_id(&self, key: &str) -> Option<&String> {
+        self.provider_ids.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v)
+    }
+}
+
+impl From<Item> for Candidate {
+    fn from(item: Item) -> Candidate {
+        // `MusicBrainzTrack` is the release track id, only newer servers
+        // have the recording id
+        let recording_mbid = item.provider_id("MusicBrainzRecording").cloned();
+
+        Candidate {
+            id: item.id,
+            title: item.name,
+            artist: item.artists.into_iter().next().or(item.album_artist).unwrap_or_default(),
+            album: item.album,
+            duration: item.run_time_ticks.map(|ticks| ticks / 10_000_000),
+            year: item.production_year,
+            recording_mbid,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ItemsResponse {
+    items: Vec<Item>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PlaylistItem {
+    id: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PlaylistsResponse {
+    items: Vec<PlaylistItem>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AuthUser {
+    id: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AuthResponse {
+    access_token: String,
+    user: AuthUser,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    id: String,
+    name: String,
+}
+
+pub struct MediaBrowserClient<S: Server> {
+    root: String,
+    token: String,
+    user_id: String,
+    client: reqwest::blocking::Client,
+    server: PhantomData<S>,
+}
+
+impl<S: Server> MediaBrowserClient<S> {
+    /// Connect using `<PREFIX>_HOST` and either `<PREFIX>_API_KEY` with
+    /// `<PREFIX>_USER` (whose library is searched and who owns the playlists)
+    /// or `<PREFIX>_USER` with `<PREFIX>_PASS` for a regular user login.
+    pub fn from_env() -> Result<MediaBrowserClient<S>> {
+        let var = |name: &str| {
+            let name = format!("{}_{name}", S::ENV_PREFIX);
+            std::env::var(&name).with_context(|| format!("{name} not set"))
+        };
+        let root = var("HOST")?;
+        let user = var("USER")?;
+
+        match var("API_KEY") {
+            Ok(api_key) => Self::with_api_key(root, api_key, &user),
+            Err(_) => Self::login(root, &user, &var("PASS").with_context(|| format!("Neither {0}_API_KEY nor {0}_PASS set", S::ENV_PREFIX))?),
+        }
+    }
+
+    fn new(root: &str) -> MediaBrowserClient<S> {
+        let root = root.trim_end_matches('/');
+        let root = if root.ends_with(S::ROUTE_PREFIX) { root.to_string() } else { format!("{root}{}", S::ROUTE_PREFIX) };
+
+        MediaBrowserClient {
+            root,
+            token: String::new(),
+            user_id: String::new(),
+            client: reqwest::blocking::Client::new(),
+            server: PhantomData,
+        }
+    }
+
+    pub fn with_api_key(root: String, api_key: String, user: &str) -> Result<MediaBrowserClient<S>> {
+        let mut mb_client = Self::new(&root);
+        mb_client.token = api_key;
+
+        let users: Vec<User> = mb_client.send(mb_client.get("/Users"))?.json()?;
+        mb_client.user_id = users.into_iter()
+            .find(|u| u.name == user)
+            .map(|u| u.id)
+            .ok_or_else(|| anyhow!("No {} user named {user}", S::NAME))?;
+
+        Ok(mb_client)
+    }
+
+    pub fn login(root: String, user: &str, password: &str) -> Result<MediaBrowserClient<S>> {
+        let mut mb_client = Self::new(&root);
+
+        let request = S::login_body(mb_client.post("/Users/AuthenticateByName"), user, password);
+        let auth: AuthResponse = mb_client.send(request).with_context(|| format!("{} login failed", S::NAME))?.json()?;
+
+        mb_client.token = auth.access_token;
+        mb_client.user_id = auth.user.id;
+        Ok(mb_client)
+    }
+
+    fn get(&self, api: &str) -> RequestBuilder {
+        S::authorize(self.client.get(format!("{}{api}", self.root)), &self.token)
+    }
+
+    fn post(&self, api: &str) -> RequestBuilder {
+        S::authorize(self.client.post(format!("{}{api}", self.root)), &self.token)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+        let res = request.send().with_context(|| format!("Failed to send {} request", S::NAME))?;
+        let status = res.status();
+
+        if !status.is_success() {
+            let body = res.text().unwrap_or_default();
+            return Err(anyhow!("{} request failed: {} - {}", S::NAME, status, body));
+        }
+        Ok(res)
+    }
+
+    fn find_playlist(&self, name: &str) -> Result<Option<String>> {
+        let request = self.get("/Items").query(&[
+            ("UserId", self.user_id.as_str()),
+            ("IncludeItemTypes", "Playlist"),
+            ("Recursive", "true"),
+            ("SearchTerm", name),
+        ]);
+        let playlists: PlaylistsResponse = self.send(request)?.json()?;
+
+        Ok(playlists.items.into_iter().find(|p| p.name == name).map(|p| p.id))
+    }
+}
+
+impl<S: Server> Target for MediaBrowserClient<S> {
+    fn override_platform(&self) -> &'static str {
+        S::PLATFORM
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("{}:{}", S::PLATFORM, self.root)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        let request = self.get("/Items").query(&[
+            ("UserId", self.user_id.as_str()),
+            ("IncludeItemTypes", "Audio"),
+            ("Recursive", "true"),
+            ("SearchTerm", query),
+            ("Fields", "ProviderIds"),
+            ("Limit", "10"),
+        ]);
+        let items: ItemsResponse = self.send(request)?.json()?;
+
+        Ok(items.items.into_iter().map(Candidate::from).collect())
+    }
+
+    // Search only looks at names so the artist is left out of the query and
+    // matched afterwards.
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        self.search(&track.title)
+    }
+
+    fn location(&self, id: &str) -> Option<String> {
+        Some(format!("{}/Audio/{id}/stream?static=true", self.root))
+    }
+
+    fn location_with_credentials(&self, id: &str) -> Option<String> {
+        Some(format!("{}/Audio/{id}/stream?static=true&api_key={}", self.root, urlencoding::encode(&self.token)))
+    }
+
+    /// Add the tracks to an existing playlist with the same name, else create
+    /// a new one.
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        match self.find_playlist(name)? {
+            Some(playlist_id) => {
+                let request = self.get(&format!("/Playlists/{playlist_id}/Items")).query(&[("UserId", &self.user_id)]);
+                let existing: ItemsResponse = self.send(request)?.json()?;
+                let new_ids = ids.into_iter()
+                    .filter(|id| !existing.items.iter().any(|item| item.id == *id))
+                    .collect::<Vec<String>>();
+
+                if !new_ids.is_empty() {
+                    let request = self.post(&format!("/Playlists/{playlist_id}/Items"))
+                        .query(&[("Ids", new_ids.join(",")), ("UserId", self.user_id.clone())]);
+                    self.send(request)?;
+                }
+            },
+            None => {
+                let request = self.post("/Playlists").query(&[
+                    ("Name", name),
+                    ("Ids", &ids.join(",")),
+                    ("UserId", &self.user_id),
+                    ("MediaType", "Audio"),
+                ]);
+                self.send(request)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_route_prefix() {
+        use crate::platform::{emby::Emby, jellyfin::Jellyfin};
+
+        assert_eq!(MediaBrowserClient::<Emby>::new("http://emby:8096/").root, "http://emby:8096/emby");
+        assert_eq!(MediaBrowserClient::<Emby>::new("http://emby:8096/emby").root, "http://emby:8096/emby");
+        assert_eq!(MediaBrowserClient::<Jellyfin>::new("http://jf:8096/").root, "http://jf:8096");
+        assert_eq!(MediaBrowserClient::<Jellyfin>::new("https://example.com/jellyfin").root, "https://example.com/jellyfin");
+    }
+
+    #[test]
+    fn maps_items() {
+        let items: ItemsResponse = serde_json::from_str(r#"{"Items": [
+            {"Id": "a1", "Name": "Get Lucky", "Artists": ["Daft Punk", "Pharrell Williams"], "AlbumArtist": "Daft Punk",
+             "Album": "Random Access Memories", "RunTimeTicks": 3690000000, "ProductionYear": 2013,
+             "ProviderIds": {"MusicBrainzTrack": "1111", "MusicBrainzRecording": "2222"}},
+            {"Id": "a2", "Name": "Halo", "AlbumArtist": "Beyoncé", "ProviderIds": {"musicbrainzrecording": "3333"}},
+            {"Id": "a3", "Name": "Untagged", "ProviderIds": {"MusicBrainzTrack": "4444"}}
+        ]}"#).unwrap();
+        let candidates = items.items.into_iter().map(Candidate::from).collect::<Vec<Candidate>>();
+
+        assert_eq!(candidates[0].artist, "Daft Punk");
+        assert_eq!(candidates[0].album.as_deref(), Some("Random Access Memories"));
+        assert_eq!(candidates[0].duration, Some(369));
+        assert_eq!(candidates[0].year, Some(2013));
+        assert_eq!(candidates[0].recording_mbid.as_deref(), Some("2222"));
+
+        assert_eq!(candidates[1].artist, "Beyoncé");
+        assert_eq!(candidates[1].recording_mbid.as_deref(), Some("3333"));
+
+        // Release track ids are no recording ids
+        assert_eq!(candidates[2].artist, "");
+        assert_eq!(candidates[2].recording_mbid, None);
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+
+// Error code MPD uses for missing songs and playlists
+const ACK_ERROR_NO_EXIST: u32 = 50;
+
+/// Error reported by MPD with an `ACK` line
+#[derive(Debug)]
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    // Lines look like `ACK [50@0] {listplaylist} No such playlist`
+    fn parse(line: &str) -> Ack {
+        let code = line.strip_prefix('[')
+            .and_then(|rest| rest.split_once('@'))
+            .and_then(|(code, _)| code.parse().ok())
+            .unwrap_or_default();
+        Ack { code, message: line.to_string() }
+    }
+}
+
+impl std::fmt::Display for Ack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MPD error: {}", self.message)
+    }
+}
+
+impl std::error::Error for Ack {}
+
+struct Connection {
+    reader: BufReader<Box<dyn Read>>,
+    writer: Box<dyn Write>,
+}
+
+impl Connection {
+    fn read_response(&mut self) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(anyhow!("MPD closed the connection"));
+            }
+            let line = line.trim_end_matches('\n');
+
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if let Some(error) = line.strip_prefix("ACK ") {
+                return Err(Ack::parse(error).into());
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<Vec<(String, String)>> {
+        self.writer.write_all(format!("{command}\n").as_bytes())?;
+        self.writer.flush()?;
+        self.read_response()
+    }
+}
+
+// Arguments are double quoted with backslash escapes
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Songs in a response are separated by their `file` key
+fn parse_songs(pairs: Vec<(String, String)>) -> Vec<Candidate> {
+    let mut songs: Vec<Candidate> = vec![];
+
+    for (key, value) in pairs {
+        if key == "file" {
+            songs.push(Candidate { id: value, ..Default::default() });
+            continue;
+        }
+
+        let Some(song) = songs.last_mut() else { continue };
+        match key.as_str() {
+            "Title" => song.title = value,
+            "Artist" if song.artist.is_empty() => song.artist = value,
+            "Album" => song.album = Some(value),
+            "duration" => song.duration = value.parse::<f64>().ok().map(|d| d as u64),
+            "Time" if song.duration.is_none() => song.duration = value.parse().ok(),
+            "Date" => song.year = value.get(..4).and_then(|y| y.parse().ok()),
+            // Taggers write the recording id here
+            "MUSICBRAINZ_TRACKID" => song.recording_mbid = Some(value),
+            _ => {},
+        }
+    }
+
+    songs
+}
+
+pub struct MpdClient {
+    host: String,
+    connection: RefCell<Connection>,
+}
+
+impl MpdClient {
+    /// Connect using `MPD_HOST` and `MPD_PORT` like the mpc client does.
+    /// `MPD_HOST` can be a hostname or a Unix socket path, optionally
+    /// prefixed by `password@`. Defaults to localhost:6600.
+    pub fn from_env() -> Result<MpdClient> {
+        let host = std::env::var("MPD_HOST").unwrap_or("localhost".to_string());
+        let port = std::env::var("MPD_PORT").unwrap_or("6600".to_string()).parse::<u16>().context("Invalid MPD_PORT")?;
+
+        match host.rsplit_once('@') {
+            Some((password, host)) => Self::connect(host, port, Some(password)),
+            None => Self::connect(&host, port, None),
+        }
+    }
+
+    pub fn connect(host: &str, port: u16, password: Option<&str>) -> Result<MpdClient> {
+        let (reader, writer): (Box<dyn Read>, Box<dyn Write>) = if host.starts_with('/') {
+            let stream = UnixStream::connect(host).with_context(|| format!("Unable to connect to MPD at {host}"))?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        } else {
+            let stream = TcpStream::connect((host, port)).with_context(|| format!("Unable to connect to MPD at {host}:{port}"))?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        };
+
+        let mut connection = Connection { reader: BufReader::new(reader), writer };
+
+        let mut greeting = String::new();
+        connection.reader.read_line(&mut greeting)?;
+        if !greeting.starts_with("OK MPD") {
+            return Err(anyhow!("Unexpected MPD greeting: {}", greeting.trim_end()));
+        }
+
+        if let Some(password) = password {
+            connection.command(&format!("password {}", quote(password))).context("MPD login failed")?;
+        }
+
+        Ok(MpdClient {
+            host: if host.starts_with('/') { host.to_string() } else { format!("{host}:{port}") },
+            connection: RefCell::new(connection),
+        })
+    }
+
+    fn command(&self, command: &str) -> Result<Vec<(String, String)>> {
+        self.connection.borrow_mut().command(command)
+    }
+
+    fn playlist_files(&self, name: &str) -> Result<Option<Vec<String>>> {
+        match self.command(&format!("listplaylist {}", quote(name))) {
+            Ok(pairs) => Ok(Some(pairs.into_iter().filter(|(k, _)| k == "file").map(|(_, v)| v).collect())),
+            // Missing playlists are reported as errors
+            Err(err) if err.downcast_ref::<Ack>().is_some_and(|ack| ack.code == ACK_ERROR_NO_EXIST) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn supports(&self, command: &str) -> Result<bool> {
+        Ok(self.command("commands")?.iter().any(|(k, v)| k == "command" && v == command))
+    }
+
+    fn queue_length(&self) -> Result<usize> {
+        let status = self.command("status")?;
+        Ok(status.iter().find(|(k, _)| k == "playlistlength").and_then(|(_, v)| v.parse().ok()).unwrap_or_default())
+    }
+}
+
+impl Target for MpdClient {
+    fn override_platform(&self) -> &'static str {
+        "mpd"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("mpd:{}", self.host)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        Ok(parse_songs(self.command(&format!("search any {}", quote(query)))?))
+    }
+
+    /// Look up tagged recording ids first, then search by title and leave the
+    /// artist to the matcher.
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(mbid) = track.recording_mbid() {
+            let songs = parse_songs(self.command(&format!("find musicbrainz_trackid {}", quote(&mbid)))?);
+            if !songs.is_empty() {
+                return Ok(songs);
+            }
+        }
+
+        Ok(parse_songs(self.command(&format!("search title {}", quote(&track.title)))?))
+    }
+
+    // Songs in the music directory have relative URIs which only mean
+    // something to this server
+    fn location(&self, id: &str) -> Option<String> {
+        id.contains("://").then(|| id.to_string())
+    }
+
+    /// Append the songs to the stored playlist `name`, creating it if needed.
+    /// Servers without `playlistadd` (like older Mopidy) get the playlist
+    /// built in the queue and saved from there, which needs an empty queue.
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        let existing = self.playlist_files(name)?.unwrap_or_default();
+        let new_ids = ids.into_iter().filter(|id| !existing.contains(id)).collect::<Vec<String>>();
+
+        if new_ids.is_empty() {
+            return Ok(());
+        }
+
+        if self.supports("playlistadd")? {
+            let adds = new_ids.iter().map(|id| format!("playlistadd {} {}", quote(name), quote(id))).collect::<Vec<String>>();
+            self.command(&format!("command_list_begin\n{}\ncommand_list_end", adds.join("\n")))?;
+            return Ok(());
+        }
+
+        if self.queue_length()? > 0 {
+            return Err(anyhow!("MPD at {} doesn't support playlistadd, clear its queue so the playlist can be built in there", self.host));
+        }
+        warn!("MPD doesn't support playlistadd, saving the playlist from the queue instead");
+
+        let mut commands = existing.iter().chain(new_ids.iter()).map(|id| format!("add {}", quote(id))).collect::<Vec<String>>();
+        if !existing.is_empty() {
+            commands.push(format!("rm {}", quote(name)));
+        }
+        commands.push(format!("save {}", quote(name)));
+
+        // Command lists stop at the first failure, so the queue is emptied
+        // again either way
+        let result = self.command(&format!("command_list_begin\n{}\ncommand_list_end", commands.join("\n")));
+        self.command("clear")?;
+        result.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(response: &str) -> Connection {
+        Connection {
+            reader: BufReader::new(Box::new(std::io::Cursor::new(response.as_bytes().to_vec()))),
+            writer: Box::new(std::io::sink()),
+        }
+    }
+
+    #[test]
+    fn parses_acks() {
+        let ack = Ack::parse("[50@0] {listplaylist} No such playlist");
+        assert_eq!(ack.code, ACK_ERROR_NO_EXIST);
+        assert_eq!(ack.to_string(), "MPD error: [50@0] {listplaylist} No such playlist");
+        assert_eq!(Ack::parse("[2@1] {playlistadd} wrong number of arguments").code, 2);
+        assert_eq!(Ack::parse("garbled").code, 0);
+
+        let err = connection("ACK [50@0] {listplaylist} No such playlist\n").command("listplaylist \"x\"").unwrap_err();
+        assert_eq!(err.downcast_ref::<Ack>().map(|ack| ack.code), Some(ACK_ERROR_NO_EXIST));
+        assert!(connection("file: a.flac\n").command("find").is_err());
+    }
+
+    #[test]
+    fn parses_songs() {
+        let response = "file: Daft Punk/RAM/08 Get Lucky.flac\n\
+                        Title: Get Lucky\n\
+                        Artist: Daft Punk\n\
+                        Artist: Pharrell Williams\n\
+                        Album: Random Access Memories\n\
+                        Date: 2013-05-17\n\
+                        Time: 369\n\
+                        duration: 369.626\n\
+                        MUSICBRAINZ_TRACKID: 8f3471b5-7e6a-48da-86a9-c1c07a0f47ae\n\
+                        file: misc/untagged.mp3\n\
+                        Time: 125\n\
+                        Title: Title: with a colon\n\
+                        OK\n";
+        let songs = parse_songs(connection(response).command("search any \"lucky\"").unwrap());
+
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs[0].id, "Daft Punk/RAM/08 Get Lucky.flac");
+        assert_eq!(songs[0].title, "Get Lucky");
+        assert_eq!(songs[0].artist, "Daft Punk");
+        assert_eq!(songs[0].album.as_deref(), Some("Random Access Memories"));
+        assert_eq!(songs[0].year, Some(2013));
+        assert_eq!(songs[0].duration, Some(369));
+        assert_eq!(songs[0].recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+
+        assert_eq!(songs[1].id, "misc/untagged.mp3");
+        assert_eq!(songs[1].title, "Title: with a colon");
+        assert_eq!(songs[1].artist, "");
+        assert_eq!(songs[1].duration, Some(125));
+        assert_eq!(songs[1].recording_mbid, None);
+
+        assert!(parse_songs(connection("OK\n").command("search any \"x\"").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn quotes_arguments() {
+        assert_eq!(quote("Get Lucky"), r#""Get Lucky""#);
+        assert_eq!(quote(r#"12" Mix"#), r#""12\" Mix""#);
+        assert_eq!(quote(r"AC\DC"), r#""AC\\DC""#);
+        assert_eq!(quote(r#"\""#), r#""\\\"""#);
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::RequestBuilder;
+
+use crate::import::{Candidate, Target};
+use crate::mbzlists::Track;
+
+const CLIENT_NAME: &str = "mbzlists-resolvers";
+
+#[derive(serde::Deserialize, Debug)]
+struct PlexGuid {
+    id: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PlexTrack {
+    rating_key: String,
+    title: String,
+    /// Track artist, only set when it differs from the album artist
+    original_title: Option<String>,
+    /// Album artist
+    grandparent_title: Option<String>,
+    /// Album
+    parent_title: Option<String>,
+    /// Duration in milliseconds
+    duration: Option<u64>,
+    parent_year: Option<u32>,
+    #[serde(rename = "Guid", default)]
+    guids: Vec<PlexGuid>,
+}
+
+impl From<PlexTrack> for Candidate {
+    fn from(plex_track: PlexTrack) -> Candidate {
+        // The Plex music agent lists external ids like mbid://<recording-mbid>
+        let recording_mbid = plex_track.guids.iter()
+            .find_map(|guid| guid.id.strip_prefix("mbid://").map(str::to_string));
+
+        Candidate {
+            id: plex_track.rating_key,
+            title: plex_track.title,
+            artist: plex_track.original_title.or(plex_track.grandparent_title).unwrap_or_default(),
+            album: plex_track.parent_title,
+            duration: plex_track.duration.map(|ms| ms / 1000),
+            year: plex_track.parent_year,
+            recording_mbid,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PlexSection {
+    key: String,
+    #[serde(rename = "type")]
+    section_type: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PlexPlaylist {
+    rating_key: String,
+    title: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MediaContainer<T> {
+    machine_identifier: Option<String>,
+    #[serde(rename = "Metadata", alias = "Directory", default = "Vec::new")]
+    items: Vec<T>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PlexResponse<T> {
+    #[serde(rename = "MediaContainer")]
+    media_container: MediaContainer<T>,
+}
+
+pub struct PlexClient {
+    root: String,
+    token: String,
+    machine_id: String,
+    music_sections: Vec<String>,
+    client: reqwest::blocking::Client,
+}
+
+impl PlexClient {
+    /// Connect using `PLEX_HOST` (like http://192.168.0.101:32400) and
+    /// `PLEX_TOKEN`.
+    pub fn from_env() -> Result<PlexClient> {
+        let root = std::env::var("PLEX_HOST").context("PLEX_HOST not set")?;
+        let token = std::env::var("PLEX_TOKEN").context("PLEX_TOKEN not set")?;
+        Self::new(root, token)
+    }
+
+    pub fn new(root: String, token: String) -> Result<PlexClient> {
+        let mut plex_client = PlexClient {
+            root: root.trim_end_matches('/').to_string(),
+            token,
+            machine_id: String::new(),
+            music_sections: vec![],
+            client: reqwest::blocking::Client::new(),
+        };
+
+        // Playlist item uris point into the server by its machine id
+        let identity: PlexResponse<serde::de::IgnoredAny> = plex_client.send(plex_client.request(reqwest::Method::GET, "/"))?.json()?;
+        plex_client.machine_id = identity.media_container.machine_identifier
+            .ok_or_else(|| anyhow!("Missing machineIdentifier in Plex response"))?;
+
+        let sections: PlexResponse<PlexSection> = plex_client.send(plex_client.request(reqwest::Method::GET, "/library/sections"))?.json()?;
+        plex_client.music_sections = sections.media_container.items.into_iter()
+            .filter(|s| s.section_type == "artist")
+            .map(|s| s.key)
+            .collect();
+
+        if plex_client.music_sections.is_empty() {
+            return Err(anyhow!("No music library found on the Plex server"));
+        }
+
+        Ok(plex_client)
+    }
+
+    fn request(&self, method: reqwest::Method, api: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{api}", self.root))
+            .header("X-Plex-Token", &self.token)
+            .header("X-Plex-Client-Identifier", CLIENT_NAME)
+            .header("X-Plex-Product", CLIENT_NAME)
+            .header("Accept", "application/json")
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+        let res = request.send().context("Failed to send Plex request")?;
+        let status = res.status();
+
+        if !status.is_success() {
+            let body = res.text().unwrap_or_default();
+            return Err(anyhow!("Plex request failed: {} - {}", status, body));
+        }
+        Ok(res)
+    }
+
+    fn items_uri(&self, ids: &[String]) -> String {
+        format!("server://{}/com.plexapp.plugins.library/library/metadata/{}", self.machine_id, ids.join(","))
+    }
+
+    fn find_playlist(&self, name: &str) -> Result<Option<String>> {
+        let request = self.request(reqwest::Method::GET, "/playlists").query(&[("playlistType", "audio")]);
+        let playlists: PlexResponse<PlexPlaylist> = self.send(request)?.json()?;
+
+        Ok(playlists.media_container.items.into_iter().find(|p| p.title == name).map(|p| p.rating_key))
+    }
+}
+
+impl Target for PlexClient {
+    fn override_platform(&self) -> &'static str {
+        "plex"
+    }
+
+    fn cache_platform(&self) -> String {
+        format!("plex:{}", self.machine_id)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        let mut candidates = vec![];
+
+        for section in &self.music_sections {
+            let request = self.request(reqwest::Method::GET, &format!("/library/sections/{section}/search"))
+                .query(&[("type", "10"), ("title", query), ("includeGuids", "1")]);
+            let tracks: PlexResponse<PlexTrack> = self.send(request)?.json()?;
+            candidates.extend(tracks.media_container.items.into_iter().map(Candidate::from));
+        }
+
+        Ok(candidates)
+    }
+
+    // Track search only looks at titles
+    fn candidates(&self, track: &Track) -> Result<Vec<Candidate>> {
+        self.search(&track.title)
+    }
+
+    /// Add the tracks to an existing playlist with the same name, else create
+    /// a new one.
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        match self.find_playlist(name)? {
+            Some(playlist_id) => {
+                let request = self.request(reqwest::Method::GET, &format!("/playlists/{playlist_id}/items"));
+                let existing: PlexResponse<PlexTrack> = self.send(request)?.json()?;
+                let new_ids = ids.into_iter()
+                    .filter(|id| !existing.media_container.items.iter().any(|t| t.rating_key == *id))
+                    .collect::<Vec<String>>();
+
+                if !new_ids.is_empty() {
+                    let request = self.request(reqwest::Method::PUT, &format!("/playlists/{playlist_id}/items"))
+                        .query(&[("uri", self.items_uri(&new_ids))]);
+                    self.send(request)?;
+                }
+            },
+            None => {
+                let request = self.request(reqwest::Method::POST, "/playlists")
+                    .query(&[("type", "audio"), ("title", name), ("smart", "0"), ("uri", &self.items_uri(&ids))]);
+                self.send(request)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_tracks() {
+        let tracks: PlexResponse<PlexTrack> = serde_json::from_str(r#"{"MediaContainer": {"size": 2, "Metadata": [
+            {"ratingKey": "101", "title": "Get Lucky", "grandparentTitle": "Daft Punk", "originalTitle": "Daft Punk feat. Pharrell Williams",
+             "parentTitle": "Random Access Memories", "duration": 369626, "parentYear": 2013,
+             "Guid": [{"id": "mbid://8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"}, {"id": "plex://track/5d07cdd4403c640290f5f0a9"}]},
+            {"ratingKey": "102", "title": "Halo", "grandparentTitle": "Beyoncé"}
+        ]}}"#).unwrap();
+        let candidates = tracks.media_container.items.into_iter().map(Candidate::from).collect::<Vec<Candidate>>();
+
+        assert_eq!(candidates[0].id, "101");
+        assert_eq!(candidates[0].artist, "Daft Punk feat. Pharrell Williams");
+        assert_eq!(candidates[0].album.as_deref(), Some("Random Access Memories"));
+        assert_eq!((candidates[0].duration, candidates[0].year), (Some(369), Some(2013)));
+        assert_eq!(candidates[0].recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+
+        assert_eq!(candidates[1].artist, "Beyoncé");
+        assert_eq!((candidates[1].duration, &candidates[1].recording_mbid), (None, &None));
+    }
+
+    #[test]
+    fn reads_containers() {
+        let sections: PlexResponse<PlexSection> = serde_json::from_str(r#"{"MediaContainer": {"Directory": [
+            {"key": "1", "type": "movie", "title": "Movies"}, {"key": "3", "type": "artist", "title": "Music"}
+        ]}}"#).unwrap();
+        assert_eq!(sections.media_container.items.iter().map(|s| (s.key.as_str(), s.section_type.as_str())).collect::<Vec<_>>(),
+                   vec![("1", "movie"), ("3", "artist")]);
+
+        // Empty containers leave out the list
+        let identity: PlexResponse<serde::de::IgnoredAny> = serde_json::from_str(r#"{"MediaContainer": {"machineIdentifier": "abc"}}"#).unwrap();
+        assert_eq!(identity.media_container.machine_identifier.as_deref(), Some("abc"));
+        assert!(identity.media_container.items.is_empty());
+    }
+}
@@ -4,21 +4,16 @@ use log::debug;
 use url::Url;
 use anyhow::{Result, Context, anyhow};
 use askama::Template;
+use base64::prelude::*;
 
-use crate::{mbzlists::{self, Track}, webapp::{PlCreatePageTemplate, PlCreatedPageTemplate}};
+use crate::{export::{SourcePlaylist, SourceTrack}, matching::{self, ArtistNames}, mbzlists::{self, Track}, musicbrainz::MusicBrainzClient, webapp::{self, PlCreatePageTemplate, PlCreatedPageTemplate}};
 
 
 const API_ROOT: &str = "https://api.spotify.com/v1";
-
-#[derive(serde::Deserialize, Debug, Clone)]
-pub struct SpotifyAlbum {
-    id: String,
-    name: String,
-}
+const PLATFORM: &str = "spotify";
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct SpotifyArtist {
-    id: String,
     name: String,
 }
 
@@ -27,7 +22,6 @@ pub struct SpotifyTrack {
     id: String,
     name: String,
     artists: Vec<SpotifyArtist>,
-    album: SpotifyAlbum,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -44,7 +38,6 @@ impl std::fmt::Display for SpotifyAPIError {
 
 #[derive(serde::Deserialize, Debug)]
 struct TracksSearchResult {
-    total: usize,
     items: Vec<SpotifyTrack>,
 }
 
@@ -56,17 +49,29 @@ enum SpotifyResponse {
 }
 
 pub struct SpotifyPlaylist {
-    id: String,
     url: String,
 }
 
-async fn resolve(track: &Track, access_token: &str) -> Result<SpotifyTrack> {
-    let query = urlencoding::encode(&format!("{} artist:{}", track.title, track.creator)).to_string();
+/// Search for the track, giving `None` when the search found no match. Errors
+/// (rate limits, expired tokens and the like) are returned as such so they
+/// don't get cached as misses.
+async fn resolve(track: &Track, artist_names: &ArtistNames, access_token: &str) -> Result<Option<SpotifyTrack>> {
+    // Field filters need a single artist
+    for artist in artist_names.search_names(track) {
+        if let Some(found_track) = search(track, &artist, artist_names, access_token).await? {
+            return Ok(Some(found_track));
+        }
+    }
+    Ok(None)
+}
+
+async fn search(track: &Track, artist: &str, artist_names: &ArtistNames, access_token: &str) -> Result<Option<SpotifyTrack>> {
+    let query = urlencoding::encode(&format!("{} artist:{artist}", track.title)).to_string();
 
     let client = reqwest::Client::new();
     let res = client
         .get(format!("{API_ROOT}/search?q={query}&type=track"))
-        .bearer_auth(&access_token)
+        .bearer_auth(access_token)
         .send()
         .await
         .context("Failed to send search request")?;
@@ -82,15 +87,19 @@ async fn resolve(track: &Track, access_token: &str) -> Result<SpotifyTrack> {
 
     match json {
         SpotifyResponse::Success { tracks } => {
-            let found_track = tracks.items[0].clone();
-
-            // We are letting go of case sensitive matching here. This might be
-            // a problem later though.
-            if found_track.name.to_lowercase() == track.title.to_lowercase() && found_track.artists[0].name.to_lowercase() == track.creator.to_lowercase() {
-                Ok(found_track)
+            let Some(found_track) = tracks.items.into_iter().next() else {
+                debug!("No results for {:?} with artist {artist}", track);
+                return Ok(None);
+            };
+
+            // Artist names on Spotify are often romanized or lack diacritics,
+            // so we accept any known alias of the artist.
+            let found_artists = found_track.artists.iter().map(|artist| artist.name.as_str()).collect::<Vec<&str>>();
+            if matching::title_matches(&found_track.name, &track.title) && artist_names.matches_artists(&found_artists) {
+                Ok(Some(found_track))
             } else {
                 debug!("Error in matching: {:?}", found_track);
-                Err(anyhow!("Error in matching: {:?}", found_track))
+                Ok(None)
             }
         },
         SpotifyResponse::Error { error } => {
@@ -100,11 +109,11 @@ async fn resolve(track: &Track, access_token: &str) -> Result<SpotifyTrack> {
     }
 }
 
-async fn create_playlist(name: &str, tracks: Vec<SpotifyTrack>, user_id: &str, access_token: &str) -> Result<SpotifyPlaylist> {
+async fn create_playlist(name: &str, track_ids: Vec<String>, user_id: &str, access_token: &str) -> Result<SpotifyPlaylist> {
     let client = reqwest::Client::new();
     let res = client
         .post(format!("{API_ROOT}/users/{user_id}/playlists"))
-        .bearer_auth(&access_token)
+        .bearer_auth(access_token)
         .json(&serde_json::json!({
             "name": name,
             "public": false,
@@ -135,14 +144,14 @@ async fn create_playlist(name: &str, tracks: Vec<SpotifyTrack>, user_id: &str, a
         .ok_or_else(|| anyhow!("Missing playlist url in response: {}", json))?;
 
     client.post(format!("{API_ROOT}/playlists/{playlist_id}/tracks"))
-        .bearer_auth(&access_token)
+        .bearer_auth(access_token)
         .json(&serde_json::json!({
-            "uris": tracks.iter().map(|t| format!("spotify:track:{}", t.id)).collect::<Vec<String>>()
+            "uris": track_ids.iter().map(|id| format!("spotify:track:{id}")).collect::<Vec<String>>()
         }))
         .send()
         .await?;
 
-    Ok(SpotifyPlaylist { id: playlist_id.to_string(), url: playlist_url.to_string() })
+    Ok(SpotifyPlaylist { url: playlist_url.to_string() })
 }
 
 async fn get_access_token(auth_code: &str) -> Result<String> {
@@ -157,7 +166,7 @@ async fn get_access_token(auth_code: &str) -> Result<String> {
     ];
 
     let client = reqwest::Client::new();
-    let auth_header = base64::encode(format!("{}:{}", client_id, client_secret));
+    let auth_header = BASE64_STANDARD.encode(format!("{}:{}", client_id, client_secret));
 
     let res = client
         .post("https://accounts.spotify.com/api/token")
@@ -291,7 +300,7 @@ struct CreateQuery {
 }
 
 #[get("/spotify/create")]
-pub async fn create(query: web::Query<CreateQuery>, session: Session) -> Result<impl Responder, error::Error> {
+pub async fn create(query: web::Query<CreateQuery>, session: Session, mb_client: web::Data<MusicBrainzClient>) -> Result<impl Responder, error::Error> {
     let mbzlists_url = query.mbzlists_url.clone();
     let access_token: Option<String> = session.get("access_token").unwrap_or(None);
     let user_id: Option<String> = session.get("user_id").unwrap_or(None);
@@ -307,16 +316,11 @@ pub async fn create(query: web::Query<CreateQuery>, session: Session) -> Result<
 
     let playlist = mbzlists::Playlist::from_url(&mbzlists_url).await.map_err(error::ErrorInternalServerError)?;
 
-    let mut sp_tracks = Vec::new();
+    let sp_track_ids = webapp::resolve_tracks(PLATFORM, PLATFORM, mb_client, playlist.tracklist.tracks, false, async |track: &Track, _: &[String], artist_names: &ArtistNames| {
+        Ok(resolve(track, artist_names, &access_token).await?.map(|sp_track| sp_track.id))
+    }).await?;
 
-    for track in playlist.tracklist.tracks {
-        match resolve(&track, &access_token).await {
-            Ok(ss_track) => sp_tracks.push(ss_track),
-            Err(_) => {}
-        }
-    }
-
-    let spotify_playlist = create_playlist(&playlist.title, sp_tracks, &user_id, &access_token).await.map_err(error::ErrorInternalServerError)?;
+    let spotify_playlist = create_playlist(&playlist.title, sp_track_ids, &user_id, &access_token).await.map_err(error::ErrorInternalServerError)?;
 
     let body = (PlCreatedPageTemplate {
         app_name: "Spotify",
@@ -327,3 +331,131 @@ pub async fn create(query: web::Query<CreateQuery>, session: Session) -> Result<
 
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
+
+#[derive(serde::Deserialize, Debug)]
+struct SpotifyExternalIds {
+    isrc: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SpotifyPlaylistTrack {
+    name: String,
+    // Missing for podcast episodes
+    #[serde(default)]
+    artists: Vec<SpotifyArtist>,
+    external_ids: Option<SpotifyExternalIds>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SpotifyPlaylistItem {
+    // Null for tracks that are no longer available
+    track: Option<SpotifyPlaylistTrack>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SpotifyPage<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SpotifyPlaylistInfo {
+    name: String,
+}
+
+// Playlists can be given as an open.spotify.com url, a spotify: uri or an id
+fn parse_playlist_id(playlist: &str) -> String {
+    if let Some(id) = playlist.strip_prefix("spotify:playlist:") {
+        return id.to_string();
+    }
+
+    Url::parse(playlist).ok()
+        .and_then(|url| {
+            let segments = url.path_segments()?.collect::<Vec<&str>>();
+            let position = segments.iter().position(|s| *s == "playlist")?;
+            segments.get(position + 1).map(|id| id.to_string())
+        })
+        .unwrap_or(playlist.to_string())
+}
+
+/// Token for the command line export. `SPOTIFY_ACCESS_TOKEN` (from a login in
+/// the webapp) can read private playlists, else the client credentials flow
+/// with the app credentials gives access to public ones.
+fn export_access_token() -> Result<String> {
+    if let Ok(token) = std::env::var("SPOTIFY_ACCESS_TOKEN") {
+        return Ok(token);
+    }
+
+    let client_id = std::env::var("SPOTIFY_CLIENT_ID").context("Missing SPOTIFY_CLIENT_ID env variable")?;
+    let client_secret = std::env::var("SPOTIFY_CLIENT_SECRET").context("Missing SPOTIFY_CLIENT_SECRET env variable")?;
+    let auth_header = BASE64_STANDARD.encode(format!("{}:{}", client_id, client_secret));
+
+    let res = reqwest::blocking::Client::new()
+        .post("https://accounts.spotify.com/api/token")
+        .header("Authorization", format!("Basic {}", auth_header))
+        .form(&[("grant_type", "client_credentials")])
+        .send()
+        .context("Failed to send token request")?;
+
+    let status = res.status();
+    let body = res.text().context("Failed to read response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Token request failed: {} - {}", status, body));
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&body).context("Failed to parse JSON response")?;
+
+    json.get("access_token")
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Missing access_token in response: {}", json))
+}
+
+fn get_blocking<T: serde::de::DeserializeOwned>(url: &str, access_token: &str) -> Result<T> {
+    let res = reqwest::blocking::Client::new()
+        .get(url)
+        .bearer_auth(access_token)
+        .send()
+        .context("Failed to send Spotify request")?;
+
+    let status = res.status();
+    let body = res.text().context("Failed to read Spotify response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Spotify request failed: {} - {}", status, body));
+    }
+
+    serde_json::from_str(&body).context("Failed to parse Spotify JSON response")
+}
+
+/// Read a Spotify playlist for export, with the ISRCs Spotify has
+pub fn read_playlist(playlist: &str) -> Result<SourcePlaylist> {
+    let access_token = export_access_token()?;
+    let playlist_id = parse_playlist_id(playlist);
+
+    let info: SpotifyPlaylistInfo = get_blocking(&format!("{API_ROOT}/playlists/{playlist_id}?fields=name"), &access_token)?;
+    let mut tracks = vec![];
+    let mut next = Some(format!("{API_ROOT}/playlists/{playlist_id}/tracks?limit=100"));
+
+    while let Some(url) = next {
+        let page: SpotifyPage<SpotifyPlaylistItem> = get_blocking(&url, &access_token)?;
+
+        for sp_track in page.items.into_iter().filter_map(|item| item.track) {
+            let Some(artist) = sp_track.artists.first() else {
+                debug!("Skipping {} without artists", sp_track.name);
+                continue;
+            };
+
+            tracks.push(SourceTrack {
+                artist: artist.name.clone(),
+                title: sp_track.name,
+                isrcs: sp_track.external_ids.and_then(|ids| ids.isrc).into_iter().collect(),
+                recording_mbid: None,
+            });
+        }
+        next = page.next;
+    }
+
+    Ok(SourcePlaylist { name: info.name, tracks })
+}
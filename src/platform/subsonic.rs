@@ -1,11 +1,47 @@
 use anyhow::{anyhow, Result};
-use crate::mbzlists::Track;
+use md5::{Digest, Md5};
+use crate::export::{SourcePlaylist, SourceTrack};
+use crate::import::{Candidate, Target};
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct SubsonicTrack {
     id: String,
     title: String,
+    #[serde(default)]
     artist: String,
+    album: Option<String>,
+    duration: Option<u64>,
+    year: Option<u32>,
+    #[serde(rename = "musicBrainzId")]
+    musicbrainz_id: Option<String>,
+    /// Only sent by OpenSubsonic servers
+    #[serde(default)]
+    isrc: Vec<String>,
+}
+
+impl From<SubsonicTrack> for Candidate {
+    fn from(ss_track: SubsonicTrack) -> Candidate {
+        Candidate {
+            id: ss_track.id,
+            title: ss_track.title,
+            artist: ss_track.artist,
+            album: ss_track.album,
+            duration: ss_track.duration,
+            year: ss_track.year,
+            recording_mbid: ss_track.musicbrainz_id.filter(|mbid| !mbid.is_empty()),
+        }
+    }
+}
+
+impl From<SubsonicTrack> for SourceTrack {
+    fn from(ss_track: SubsonicTrack) -> SourceTrack {
+        SourceTrack {
+            title: ss_track.title,
+            artist: ss_track.artist,
+            isrcs: ss_track.isrc,
+            recording_mbid: ss_track.musicbrainz_id.filter(|mbid| !mbid.is_empty()),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -18,7 +54,28 @@ struct SubsonicResponseWrapper {
 struct SubsonicResponse {
     status: String,
     #[serde(rename = "searchResult2")]
-    search_results2: Option<SubsonicSearchResult2>,
+    search_result2: Option<SubsonicSearchResult2>,
+    playlists: Option<SubsonicPlaylists>,
+    playlist: Option<SubsonicPlaylist>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SubsonicPlaylistInfo {
+    id: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SubsonicPlaylists {
+    #[serde(default)]
+    playlist: Vec<SubsonicPlaylistInfo>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SubsonicPlaylist {
+    name: String,
+    #[serde(default)]
+    entry: Vec<SubsonicTrack>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -44,34 +101,78 @@ impl SubsonicClient {
     }
 
     fn send_request(&self, api: &str, query_params: &str) -> Result<reqwest::blocking::Response> {
-        let url = format!("{}{}?u={}&p={}&v={}&c={}&f=json&{}", self.root, api, self.user, self.password, self.version, self.client, query_params);
+        let url = format!(
+            "{}{}?u={}&p={}&v={}&c={}&f=json&{}",
+            self.root, api, urlencoding::encode(&self.user), urlencoding::encode(&self.password), self.version, self.client, query_params
+        );
         Ok(reqwest::blocking::get(url)?)
     }
 
-    pub fn resolve(&self, track: &Track) -> Option<SubsonicTrack> {
-        let query = format!("{} {}", track.title, track.creator);
-        let response = self.send_request("/search2", &format!("query={}", urlencoding::encode(&query))).unwrap();
-        let output = response.json::<SubsonicResponseWrapper>().unwrap();
-        if let Some(SubsonicSearchResult2 { song: Some(ss_tracks) }) = output.subsonic_response.search_results2 {
-            if ss_tracks.is_empty() {
-                None
-            } else {
-                let ss_track = ss_tracks[0].clone();
-                // Final check to ensure search quality
-                if ss_track.title == track.title && ss_track.artist == track.creator {
-                    Some(ss_tracks[0].clone())
-                } else {
-                    None
-                }
-            }
-        } else {
-            None
+    /// Token authentication parameters (API 1.13.0 and later), so the
+    /// password itself doesn't end up in written playlists
+    fn token_params(&self) -> String {
+        let salt = rand::random::<[u8; 8]>().iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let token = Md5::digest(format!("{}{salt}", self.password).as_bytes()).iter().map(|b| format!("{b:02x}")).collect::<String>();
+        format!("u={}&t={token}&s={salt}&v=1.13.0&c={}", urlencoding::encode(&self.user), self.client)
+    }
+
+    /// Read the playlist with the given id or name
+    pub fn read_playlist(&self, playlist: &str) -> Result<SourcePlaylist> {
+        let output = self.send_request("/getPlaylists", "")?.json::<SubsonicResponseWrapper>()?;
+        let playlists = output.subsonic_response.playlists.map(|p| p.playlist).unwrap_or_default();
+        let id = playlists.into_iter()
+            .find(|p| p.id == playlist || p.name == playlist)
+            .map(|p| p.id)
+            .ok_or_else(|| anyhow!("No playlist {playlist} on the Subsonic server"))?;
+
+        let output = self.send_request("/getPlaylist", &format!("id={}", urlencoding::encode(&id)))?.json::<SubsonicResponseWrapper>()?;
+        let ss_playlist = output.subsonic_response.playlist.ok_or_else(|| anyhow!("Missing playlist in Subsonic response"))?;
+
+        Ok(SourcePlaylist {
+            name: ss_playlist.name,
+            tracks: ss_playlist.entry.into_iter().map(SourceTrack::from).collect(),
+        })
+    }
+}
+
+impl Target for SubsonicClient {
+    // Overrides apply to all Subsonic servers
+    fn override_platform(&self) -> &'static str {
+        "subsonic"
+    }
+
+    // Song ids are only valid on one server
+    fn cache_platform(&self) -> String {
+        format!("subsonic:{}", self.root)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Candidate>> {
+        let params = format!("query={}&songCount=10&albumCount=0&artistCount=0", urlencoding::encode(query));
+        let response = self.send_request("/search2", &params)?;
+        let output = response.json::<SubsonicResponseWrapper>()?;
+
+        match output.subsonic_response.search_result2 {
+            Some(SubsonicSearchResult2 { song: Some(ss_tracks) }) => Ok(ss_tracks.into_iter().map(Candidate::from).collect()),
+            _ => Ok(vec![]),
         }
     }
 
-    pub fn create_playlist(&self, name: String, tracks: Vec<SubsonicTrack>) -> Result<()> {
-        let ids = tracks.iter().map(|t| format!("songId={}", t.id)).collect::<Vec<String>>().join("&");
-        let response = self.send_request("/createPlaylist", &format!("name={}&{ids}", urlencoding::encode(&name)))?;
+    // Lower ranked results are only offered in interactive review
+    fn top_result_only(&self) -> bool {
+        true
+    }
+
+    fn location(&self, id: &str) -> Option<String> {
+        Some(format!("{}/stream?id={}", self.root, urlencoding::encode(id)))
+    }
+
+    fn location_with_credentials(&self, id: &str) -> Option<String> {
+        Some(format!("{}/stream?{}&id={}", self.root, self.token_params(), urlencoding::encode(id)))
+    }
+
+    fn create_playlist(&self, name: &str, ids: Vec<String>) -> Result<()> {
+        let ids = ids.iter().map(|id| format!("songId={}", urlencoding::encode(id))).collect::<Vec<String>>().join("&");
+        let response = self.send_request("/createPlaylist", &format!("name={}&{ids}", urlencoding::encode(name)))?;
         let output = response.json::<SubsonicResponseWrapper>()?;
         if output.subsonic_response.status == "ok" {
             Ok(())
@@ -80,3 +181,49 @@ impl SubsonicClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_search_results() {
+        let output: SubsonicResponseWrapper = serde_json::from_str(r#"{"subsonic-response": {"status": "ok", "version": "1.16.1", "searchResult2": {"song": [
+            {"id": "tr-1", "title": "Get Lucky", "artist": "Daft Punk", "album": "Random Access Memories", "duration": 369, "year": 2013,
+             "musicBrainzId": "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae", "isrc": ["USQX91300108"]},
+            {"id": "tr-2", "title": "Untagged", "musicBrainzId": ""}
+        ]}}}"#).unwrap();
+        let ss_tracks = output.subsonic_response.search_result2.and_then(|result| result.song).unwrap();
+        let candidates = ss_tracks.iter().cloned().map(Candidate::from).collect::<Vec<Candidate>>();
+
+        assert_eq!(candidates[0].to_string(), "Daft Punk - Get Lucky [Random Access Memories, 6:09, 2013]");
+        assert_eq!(candidates[0].recording_mbid.as_deref(), Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"));
+        assert_eq!(candidates[1].to_string(), " - Untagged");
+        assert_eq!(candidates[1].recording_mbid, None);
+
+        let source_track = SourceTrack::from(ss_tracks[0].clone());
+        assert_eq!(source_track.isrcs, vec!["USQX91300108"]);
+
+        // Servers leave out empty results
+        let output: SubsonicResponseWrapper = serde_json::from_str(r#"{"subsonic-response": {"status": "ok", "searchResult2": {}}}"#).unwrap();
+        assert!(output.subsonic_response.search_result2.unwrap().song.is_none());
+    }
+
+    #[test]
+    fn locates_tracks() {
+        let ss_client = SubsonicClient::new("https://music.example.com/rest".to_string(), "me & you".to_string(), "s3cret".to_string());
+
+        assert_eq!(ss_client.location("al-1/tr 2").as_deref(), Some("https://music.example.com/rest/stream?id=al-1%2Ftr%202"));
+
+        let location = url::Url::parse(&ss_client.location_with_credentials("tr-2").unwrap()).unwrap();
+        let params = location.query_pairs().into_owned().collect::<std::collections::HashMap<String, String>>();
+        assert_eq!(location.path(), "/rest/stream");
+        assert_eq!((params["u"].as_str(), params["id"].as_str(), params["v"].as_str()), ("me & you", "tr-2", "1.13.0"));
+        assert!(!params.contains_key("p") && !location.as_str().contains("s3cret"), "{location}");
+
+        // The token is the hex MD5 of password and salt
+        let token = Md5::digest(format!("s3cret{}", params["s"]).as_bytes()).iter().map(|b| format!("{b:02x}")).collect::<String>();
+        assert_eq!(params["t"], token);
+        assert_eq!(params["s"].len(), 16);
+    }
+}
@@ -0,0 +1,348 @@
+use actix_session::Session;
+use actix_web::{get, web, error, HttpResponse, Responder};
+use base64::prelude::*;
+use log::debug;
+use sha2::{Digest, Sha256};
+use url::Url;
+use anyhow::{Result, Context, anyhow};
+use askama::Template;
+
+use crate::{matching::{self, ArtistNames}, mbzlists::{self, Track}, musicbrainz::MusicBrainzClient, webapp::{self, PlCreatePageTemplate, PlCreatedPageTemplate}};
+
+
+const API_ROOT: &str = "https://openapi.tidal.com/v2";
+const PLATFORM: &str = "tidal";
+// Maximum number of items Tidal accepts per add-to-playlist request
+const BATCH_SIZE: usize = 20;
+
+#[derive(serde::Deserialize, Debug)]
+struct TidalResource {
+    #[serde(default)]
+    attributes: serde_json::Value,
+}
+
+impl TidalResource {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).and_then(|v| v.as_str())
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TidalDocument<T> {
+    data: T,
+    #[serde(default)]
+    included: Vec<TidalResource>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ResourceId {
+    id: String,
+}
+
+fn country_code() -> String {
+    std::env::var("TIDAL_COUNTRY_CODE").unwrap_or("US".to_string())
+}
+
+async fn api_get<T: serde::de::DeserializeOwned>(url: &str, access_token: &str) -> Result<T> {
+    let client = reqwest::Client::new();
+    let res = client
+        .get(url)
+        .bearer_auth(access_token)
+        .header("Accept", "application/vnd.api+json")
+        .send()
+        .await
+        .context("Failed to send Tidal request")?;
+
+    let status = res.status();
+    let body = res.text().await.context("Failed to read Tidal response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Tidal request failed: {} - {}", status, body));
+    }
+
+    serde_json::from_str(&body).context("Failed to parse Tidal JSON response")
+}
+
+async fn api_post(url: &str, body: serde_json::Value, access_token: &str) -> Result<String> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(url)
+        .bearer_auth(access_token)
+        .header("Content-Type", "application/vnd.api+json")
+        .body(body.to_string())
+        .send()
+        .await
+        .context("Failed to send Tidal request")?;
+
+    let status = res.status();
+    let body = res.text().await.context("Failed to read Tidal response body")?;
+
+    if !status.is_success() {
+        return Err(anyhow!("Tidal request failed: {} - {}", status, body));
+    }
+
+    Ok(body)
+}
+
+/// Resolve by ISRC first, then fall back to a text search checked against the
+/// track title and artists. Gives `None` when nothing matched, failed
+/// requests are returned as errors.
+async fn resolve(track: &Track, isrcs: &[String], artist_names: &ArtistNames, access_token: &str) -> Result<Option<String>> {
+    let country_code = country_code();
+
+    for isrc in isrcs {
+        let url = format!("{API_ROOT}/tracks?countryCode={country_code}&filter[isrc]={isrc}");
+        let doc: TidalDocument<Vec<ResourceId>> = api_get(&url, access_token).await?;
+        match doc.data.into_iter().next() {
+            Some(resource) => return Ok(Some(resource.id)),
+            None => debug!("No Tidal track for ISRC {isrc}"),
+        }
+    }
+
+    for artist in artist_names.search_names(track) {
+        if let Some(track_id) = search(track, &artist, artist_names, &country_code, access_token).await? {
+            return Ok(Some(track_id));
+        }
+    }
+    Ok(None)
+}
+
+async fn search(track: &Track, artist: &str, artist_names: &ArtistNames, country_code: &str, access_token: &str) -> Result<Option<String>> {
+    let query = urlencoding::encode(&format!("{} {artist}", track.title)).to_string();
+    let url = format!("{API_ROOT}/searchResults/{query}/relationships/tracks?countryCode={country_code}");
+    let results: TidalDocument<Vec<ResourceId>> = api_get(&url, access_token).await?;
+    let Some(track_id) = results.data.into_iter().next().map(|r| r.id) else {
+        debug!("No results for {:?} with artist {artist}", track);
+        return Ok(None);
+    };
+
+    let url = format!("{API_ROOT}/tracks/{track_id}?countryCode={country_code}&include=artists");
+    let found_track: TidalDocument<TidalResource> = api_get(&url, access_token).await?;
+
+    let title_ok = found_track.data.attribute("title").is_some_and(|title| matching::title_matches(title, &track.title));
+    let found_artists = found_track.included.iter()
+        .filter_map(|r| r.attribute("name"))
+        .collect::<Vec<&str>>();
+    let artist_ok = artist_names.matches_artists(&found_artists);
+
+    if title_ok && artist_ok {
+        Ok(Some(track_id))
+    } else {
+        debug!("Error in matching: {:?}", found_track);
+        Ok(None)
+    }
+}
+
+async fn create_playlist(name: &str, track_ids: Vec<String>, access_token: &str) -> Result<String> {
+    let body = api_post(&format!("{API_ROOT}/playlists?countryCode={}", country_code()), serde_json::json!({
+        "data": {
+            "type": "playlists",
+            "attributes": {
+                "name": name,
+                "description": "Imported from mbzlists",
+                "accessType": "UNLISTED"
+            }
+        }
+    }), access_token).await?;
+
+    let playlist: TidalDocument<ResourceId> = serde_json::from_str(&body).context("Failed to parse playlist JSON response")?;
+    let playlist_id = playlist.data.id;
+
+    for batch in track_ids.chunks(BATCH_SIZE) {
+        let items = batch.iter().map(|id| serde_json::json!({ "id": id, "type": "tracks" })).collect::<Vec<_>>();
+        api_post(&format!("{API_ROOT}/playlists/{playlist_id}/relationships/items"), serde_json::json!({ "data": items }), access_token).await?;
+    }
+
+    Ok(format!("https://tidal.com/playlist/{playlist_id}"))
+}
+
+// PKCE verifier and its S256 challenge
+fn pkce_pair() -> (String, String) {
+    let verifier = BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
+
+async fn get_access_token(auth_code: &str, code_verifier: &str) -> Result<String> {
+    let client_id = std::env::var("TIDAL_CLIENT_ID").context("Missing TIDAL_CLIENT_ID env variable")?;
+    let redirect_uri = std::env::var("TIDAL_REDIRECT_URI").context("Missing TIDAL_REDIRECT_URI env variable")?;
+
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("client_id", &client_id),
+        ("code", auth_code),
+        ("redirect_uri", &redirect_uri),
+        ("code_verifier", code_verifier),
+    ];
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post("https://auth.tidal.com/v1/oauth2/token")
+        .form(&params)
+        .send()
+        .await
+        .context("Failed to send token request")?;
+
+    let status = res.status();
+    let body = res.text().await.context("Failed to read response body")?;
+
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Token exchange failed: {} - {}", status, body));
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&body).context("Failed to parse JSON response")?;
+
+    let token = json
+        .get("access_token")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing access_token in response: {}", json))?;
+
+    Ok(token.to_string())
+}
+
+#[derive(serde::Deserialize)]
+struct LoginQuery {
+    mbzlists_url: Option<String>,
+}
+
+#[get("/tidal/login")]
+pub async fn login(query: web::Query<LoginQuery>, session: Session) -> Result<impl Responder, error::Error> {
+    let client_id = std::env::var("TIDAL_CLIENT_ID").map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Missing TIDAL_CLIENT_ID env variable"))
+    })?;
+    let redirect_uri = std::env::var("TIDAL_REDIRECT_URI").map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Missing TIDAL_REDIRECT_URI env variable"))
+    })?;
+
+    if let Some(mbzlists_url) = &query.mbzlists_url {
+        session.insert("mbzlists_url", mbzlists_url).map_err(|_| {
+            error::ErrorInternalServerError(anyhow!("Unable to set session variable `mbzlists_url`"))
+        })?;
+    }
+
+    // The verifier stays with the user until the callback exchanges the code
+    let (code_verifier, code_challenge) = pkce_pair();
+    session.insert("tidal_code_verifier", &code_verifier).map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Unable to set session variable `tidal_code_verifier`"))
+    })?;
+
+    let auth_url = Url::parse_with_params(
+        "https://login.tidal.com/authorize",
+        &[
+            ("client_id", &client_id),
+            ("response_type", &"code".to_string()),
+            ("redirect_uri", &redirect_uri),
+            ("scope", &"playlists.read playlists.write search.read user.read".to_string()),
+            ("code_challenge_method", &"S256".to_string()),
+            ("code_challenge", &code_challenge),
+        ],
+    ).map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Unable to create auth_url"))
+    })?;
+
+    Ok(HttpResponse::Found()
+        .append_header(("Location", auth_url.to_string()))
+        .finish())
+}
+
+#[derive(serde::Deserialize)]
+struct AuthQuery {
+    code: String,
+}
+
+#[get("/tidal/callback")]
+pub async fn callback(query: web::Query<AuthQuery>, session: Session) -> Result<impl Responder, error::Error> {
+    let code_verifier: String = session.get("tidal_code_verifier").unwrap_or(None).ok_or_else(|| {
+        error::ErrorBadRequest(anyhow!("Missing PKCE verifier, start again from /tidal/login"))
+    })?;
+
+    let access_token = get_access_token(&query.code, &code_verifier).await.map_err(error::ErrorInternalServerError)?;
+    session.remove("tidal_code_verifier");
+    session.insert("tidal_access_token", &access_token).map_err(|_| {
+        error::ErrorInternalServerError(anyhow!("Unable to set session variable `tidal_access_token`"))
+    })?;
+
+    if let Some(mbzlists_url) = session.get::<String>("mbzlists_url").unwrap_or(None) {
+        let create_url = format!("/tidal/create?mbzlists_url={}", mbzlists_url);
+        return Ok(HttpResponse::Found().append_header(("Location", create_url)).finish());
+    }
+
+    let body = (PlCreatePageTemplate {
+        app_name: "Tidal",
+        app_slug: "tidal",
+    })
+        .render()
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[derive(serde::Deserialize)]
+struct CreateQuery {
+    mbzlists_url: String,
+}
+
+#[get("/tidal/create")]
+pub async fn create(query: web::Query<CreateQuery>, session: Session, mb_client: web::Data<MusicBrainzClient>) -> Result<impl Responder, error::Error> {
+    let mbzlists_url = query.mbzlists_url.clone();
+    let access_token: Option<String> = session.get("tidal_access_token").unwrap_or(None);
+
+    let Some(access_token) = access_token else {
+        return Ok(HttpResponse::Found()
+            .append_header(("Location", format!("/tidal/login?mbzlists_url={mbzlists_url}")))
+            .finish());
+    };
+
+    let playlist = mbzlists::Playlist::from_url(&mbzlists_url).await.map_err(error::ErrorInternalServerError)?;
+
+    let td_track_ids = webapp::resolve_tracks(PLATFORM, PLATFORM, mb_client, playlist.tracklist.tracks, true, async |track: &Track, isrcs: &[String], artist_names: &ArtistNames| {
+        resolve(track, isrcs, artist_names, &access_token).await
+    }).await?;
+
+    let playlist_url = create_playlist(&playlist.title, td_track_ids, &access_token).await.map_err(error::ErrorInternalServerError)?;
+
+    let body = (PlCreatedPageTemplate {
+        app_name: "Tidal",
+        playlist_url: &playlist_url,
+    })
+        .render()
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makes_pkce_pairs() {
+        let (verifier, challenge) = pkce_pair();
+
+        // RFC 7636 wants 43 to 128 unreserved characters
+        assert_eq!(verifier.len(), 43);
+        assert!(verifier.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'), "{verifier}");
+        assert_eq!(challenge, BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())));
+        assert_ne!(pkce_pair().0, verifier);
+    }
+
+    #[test]
+    fn reads_documents() {
+        let doc: TidalDocument<TidalResource> = serde_json::from_str(r#"{
+            "data": {"id": "28048253", "type": "tracks", "attributes": {"title": "Get Lucky", "isrc": "USQX91300108", "duration": "PT6M9S"}},
+            "included": [
+                {"id": "8847", "type": "artists", "attributes": {"name": "Daft Punk"}},
+                {"id": "1566", "type": "artists", "attributes": {"name": "Pharrell Williams"}},
+                {"id": "9", "type": "albums"}
+            ]
+        }"#).unwrap();
+
+        assert_eq!(doc.data.attribute("title"), Some("Get Lucky"));
+        assert_eq!(doc.data.attribute("missing"), None);
+        assert_eq!(doc.included.iter().filter_map(|r| r.attribute("name")).collect::<Vec<&str>>(), vec!["Daft Punk", "Pharrell Williams"]);
+
+        let results: TidalDocument<Vec<ResourceId>> = serde_json::from_str(r#"{"data": [{"id": "28048253", "type": "tracks"}]}"#).unwrap();
+        assert_eq!(results.data[0].id, "28048253");
+        assert!(results.included.is_empty());
+    }
+}
@@ -4,9 +4,15 @@ use serde::Deserialize;
 use url::Url;
 use askama::Template;
 use anyhow::{Context, Result, anyhow};
+use log::warn;
 
+use crate::cache::ResolutionCache;
+use crate::export::{SourcePlaylist, SourceTrack};
+use crate::overrides::Overrides;
 use crate::webapp::{PlCreatePageTemplate, PlCreatedPageTemplate};
 
+const PLATFORM: &str = "youtube";
+
 
 struct YouTubeVideo {
     id: String,
@@ -14,9 +20,6 @@ struct YouTubeVideo {
 
 struct YouTubePlaylist {
     id: String,
-    title: String,
-    description: String,
-    is_private: bool,
 }
 
 async fn resolve(title: &str, artist: &str, access_token: &str) -> Result<YouTubeVideo> {
@@ -87,12 +90,7 @@ async fn create_playlist(title: &str, access_token: &str) -> Result<YouTubePlayl
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow!("Missing playlist ID in response"))?;
 
-    Ok(YouTubePlaylist {
-        id: playlist_id.to_string(),
-        title: title.to_string(),
-        description: "Imported from mbzlists".to_string(),
-        is_private: true,
-    })
+    Ok(YouTubePlaylist { id: playlist_id.to_string() })
 }
 
 async fn add_video_to_playlist(playlist: &YouTubePlaylist, video: &YouTubeVideo, access_token: &str) -> Result<()> {
@@ -248,12 +246,38 @@ pub async fn create(query: web::Query<CreateQuery>, session: Session) -> Result<
 
     let access_token = access_token.unwrap();
 
-    let playlist = crate::mbzlists::Playlist::from_url(&mbzlists_url).await.map_err(error::ErrorInternalServerError)?;
+    let playlist = crate::mbzlists::Playlist::from_url(mbzlists_url).await.map_err(error::ErrorInternalServerError)?;
     let yt_playlist = create_playlist(&playlist.title, &access_token).await.map_err(error::ErrorInternalServerError)?;
 
+    let cache = ResolutionCache::open_default()
+        .inspect_err(|err| warn!("Resolution cache unavailable: {err}"))
+        .ok();
+
+    let overrides = Overrides::load_default().map_err(error::ErrorInternalServerError)?;
+
     for track in playlist.tracklist.tracks {
+        if let Some(id) = overrides.lookup(PLATFORM, &track) {
+            add_video_to_playlist(&yt_playlist, &YouTubeVideo { id }, &access_token).await.map_err(error::ErrorInternalServerError)?;
+            continue;
+        }
+
+        // Search quota is the scarce resource on YouTube so hits are reused
+        // from earlier runs.
+        if let Some(entry) = cache.as_ref().and_then(|c| c.get(PLATFORM, &track).ok().flatten())
+            && let Some(id) = entry.platform_id {
+            let video = YouTubeVideo { id };
+            add_video_to_playlist(&yt_playlist, &video, &access_token).await.map_err(error::ErrorInternalServerError)?;
+            continue;
+        }
+
         match resolve(&track.title, &track.creator, &access_token).await {
-            Ok(video) => add_video_to_playlist(&yt_playlist, &video, &access_token).await.map_err(error::ErrorInternalServerError)?,
+            Ok(video) => {
+                if let Some(cache) = &cache
+                    && let Err(err) = cache.put(PLATFORM, &track, Some(&video.id)) {
+                    warn!("Unable to write to resolution cache: {err}");
+                }
+                add_video_to_playlist(&yt_playlist, &video, &access_token).await.map_err(error::ErrorInternalServerError)?
+            },
             // This is a little aggressive, but there are very less chance of a
             // youtube search not returning anything in normal cases
             Err(err) => return Err(error::ErrorInternalServerError(err))
@@ -270,3 +294,152 @@ pub async fn create(query: web::Query<CreateQuery>, session: Session) -> Result<
 
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
+
+#[derive(Deserialize, Debug)]
+struct YouTubeSnippet {
+    title: String,
+    #[serde(rename = "videoOwnerChannelTitle")]
+    channel_title: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct YouTubeItem {
+    snippet: YouTubeSnippet,
+}
+
+#[derive(Deserialize, Debug)]
+struct YouTubePage {
+    items: Vec<YouTubeItem>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+// Playlists can be given as a url with a `list` parameter or as an id
+fn parse_playlist_id(playlist: &str) -> String {
+    Url::parse(playlist).ok()
+        .and_then(|url| url.query_pairs().find(|(k, _)| k == "list").map(|(_, v)| v.to_string()))
+        .unwrap_or(playlist.to_string())
+}
+
+/// Guess artist and title of a video. Auto generated music videos come from
+/// "<Artist> - Topic" channels, others are usually titled "Artist - Title".
+fn parse_video(snippet: YouTubeSnippet) -> SourceTrack {
+    let channel = snippet.channel_title.unwrap_or_default();
+
+    let (artist, title) = match (channel.strip_suffix(" - Topic"), snippet.title.split_once(" - ")) {
+        (Some(artist), _) => (artist.to_string(), snippet.title.clone()),
+        (None, Some((artist, title))) => (artist.to_string(), title.to_string()),
+        (None, None) => (channel.clone(), snippet.title.clone()),
+    };
+
+    SourceTrack { title: strip_decorations(&title), artist, ..Default::default() }
+}
+
+// Words in trailing brackets that describe the video rather than the song
+const DECORATIONS: [&str; 9] = ["official", "video", "audio", "lyric", "lyrics", "visualizer", "hd", "4k", "mv"];
+
+/// Drop trailing decorations like (Official Video) or [Lyrics] from a video
+/// title. Brackets that are part of the song title, like (Remix) or "(Don't
+/// Fear) The Reaper", are kept.
+fn strip_decorations(title: &str) -> String {
+    let mut title = title.trim();
+
+    while let Some(open) = title.strip_suffix([')', ']']).and_then(|rest| rest.rfind(['(', '['])) {
+        let words = title[open + 1..title.len() - 1].to_lowercase();
+        if open == 0 || !words.split(|c: char| !c.is_alphanumeric()).any(|word| DECORATIONS.contains(&word)) {
+            break;
+        }
+        title = title[..open].trim_end();
+    }
+    title.to_string()
+}
+
+/// Read a YouTube playlist for export. Uses `GOOGLE_ACCESS_TOKEN` (from a
+/// login in the webapp) if set, else `GOOGLE_API_KEY` for public playlists.
+pub fn read_playlist(playlist: &str) -> Result<SourcePlaylist> {
+    let playlist_id = parse_playlist_id(playlist);
+    let client = reqwest::blocking::Client::new();
+
+    let get = |url: &str, params: &[(&str, &str)]| -> Result<serde_json::Value> {
+        let mut request = client.get(url).query(params);
+        request = match std::env::var("GOOGLE_ACCESS_TOKEN") {
+            Ok(token) => request.bearer_auth(token),
+            Err(_) => request.query(&[("key", std::env::var("GOOGLE_API_KEY").context("Neither GOOGLE_ACCESS_TOKEN nor GOOGLE_API_KEY set")?)]),
+        };
+
+        let res = request.send().context("Failed to send YouTube request")?;
+        let status = res.status();
+        let body = res.text().context("Failed to read YouTube response body")?;
+
+        if status != reqwest::StatusCode::OK {
+            return Err(anyhow!("YouTube request failed: {} - {}", status, body));
+        }
+        serde_json::from_str(&body).context("Failed to parse YouTube JSON response")
+    };
+
+    let info = get("https://www.googleapis.com/youtube/v3/playlists", &[("part", "snippet"), ("id", &playlist_id)])?;
+    let name = info["items"].get(0)
+        .and_then(|item| item["snippet"]["title"].as_str())
+        .ok_or_else(|| anyhow!("No YouTube playlist {playlist_id}"))?
+        .to_string();
+
+    let mut tracks = vec![];
+    let mut page_token = String::new();
+
+    loop {
+        let page = get("https://www.googleapis.com/youtube/v3/playlistItems", &[
+            ("part", "snippet"),
+            ("playlistId", &playlist_id),
+            ("maxResults", "50"),
+            ("pageToken", &page_token),
+        ])?;
+        let page: YouTubePage = serde_json::from_value(page).context("Unexpected playlistItems response")?;
+
+        // Private and deleted videos have no channel
+        tracks.extend(page.items.into_iter().filter(|item| item.snippet.channel_title.is_some()).map(|item| parse_video(item.snippet)));
+
+        match page.next_page_token {
+            Some(token) => page_token = token,
+            None => break,
+        }
+    }
+
+    Ok(SourcePlaylist { name, tracks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_videos() {
+        let cases = [
+            ("Daft Punk - Get Lucky (Official Video)", Some("Some Channel"), "Daft Punk", "Get Lucky"),
+            ("Get Lucky", Some("Daft Punk - Topic"), "Daft Punk", "Get Lucky"),
+            ("Get Lucky (Radio Edit) [Official Audio]", Some("Daft Punk - Topic"), "Daft Punk", "Get Lucky (Radio Edit)"),
+            ("Beyoncé - Halo [Lyrics] (HD)", None, "Beyoncé", "Halo"),
+            ("Blue Öyster Cult - (Don't Fear) The Reaper", None, "Blue Öyster Cult", "(Don't Fear) The Reaper"),
+            ("Avicii - Levels (Skrillex Remix)", Some("Avicii"), "Avicii", "Levels (Skrillex Remix)"),
+            ("Queen – Live at Wembley", Some("Queen Official"), "Queen Official", "Queen – Live at Wembley"),
+            ("Halo (Official Music Video)", Some("BeyonceVEVO"), "BeyonceVEVO", "Halo"),
+        ];
+
+        for (title, channel, artist, track_title) in cases {
+            let track = parse_video(YouTubeSnippet { title: title.to_string(), channel_title: channel.map(str::to_string) });
+            assert_eq!((track.artist.as_str(), track.title.as_str()), (artist, track_title), "{title}");
+        }
+    }
+
+    #[test]
+    fn parses_playlist_ids() {
+        let cases = [
+            ("https://www.youtube.com/playlist?list=PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG", "PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG"),
+            ("https://music.youtube.com/watch?v=5NV6Rdv1a3I&list=PLx0sYbCqOb8&index=2", "PLx0sYbCqOb8"),
+            ("PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG", "PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG"),
+        ];
+
+        for (playlist, id) in cases {
+            assert_eq!(parse_playlist_id(playlist), id, "{playlist}");
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::mbzlists::Playlist;
+
+/// Where mbzlists playlists are loaded from by view id. Local sources make
+/// batch runs possible without hitting the network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListSource {
+    /// An mbzlists server, by host (like mbzlists.com) or base url
+    Server(String),
+    /// A directory with one `<view-id>.xspf` or `<view-id>.jspf` file per list
+    Directory(PathBuf),
+    /// An exported archive (tar, optionally gzipped) with files named like in
+    /// a directory source, at any depth
+    Archive(PathBuf),
+}
+
+impl ListSource {
+    /// Existing directories and archives are local sources, hosts (like
+    /// mbzlists.com or localhost:8000) and http(s) urls are servers. Paths
+    /// (starting with `/`, `.` or `~`) have to exist, they are never taken
+    /// as a host.
+    pub fn parse(spec: &str) -> Result<ListSource> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return Ok(ListSource::Server(spec.trim_end_matches('/').to_string()));
+        }
+
+        let path = match spec.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().ok_or_else(|| anyhow!("Unable to find the home directory for {spec}"))?.join(rest),
+            None => PathBuf::from(spec),
+        };
+
+        if path.is_dir() {
+            Ok(ListSource::Directory(path))
+        } else if path.is_file() {
+            if is_list_file(&path) {
+                return Err(anyhow!("{spec} is a single list, pass it as the playlist file instead of a source"));
+            }
+            Ok(ListSource::Archive(path))
+        } else if !spec.starts_with(['/', '.', '~']) && is_host(spec) {
+            Ok(ListSource::Server(spec.to_string()))
+        } else {
+            Err(anyhow!("No directory or archive at {spec}"))
+        }
+    }
+
+    /// Source set in `MBZLISTS_SOURCE`, if any
+    pub fn from_env() -> Result<Option<ListSource>> {
+        std::env::var("MBZLISTS_SOURCE").ok()
+            .filter(|spec| !spec.is_empty())
+            .map(|spec| ListSource::parse(&spec).context("Invalid MBZLISTS_SOURCE"))
+            .transpose()
+    }
+
+    pub fn is_local(&self) -> bool {
+        !matches!(self, ListSource::Server(_))
+    }
+
+    /// Load the list with the given view id. Servers are queried with a
+    /// blocking request, so use [`Playlist::from_view_id`] from async code.
+    pub fn load(&self, view_id: &str) -> Result<Playlist> {
+        check_view_id(view_id)?;
+
+        match self {
+            ListSource::Server(host) => {
+                let response = reqwest::blocking::get(list_url(host, view_id))?.error_for_status()?;
+                let body = String::from_utf8(response.bytes()?.to_vec()).context("mbzlists response is not valid UTF-8")?;
+                Playlist::parse(&body)
+            },
+            ListSource::Directory(dir) => {
+                let path = ["xspf", "jspf"].iter()
+                    .map(|ext| dir.join(format!("{view_id}.{ext}")))
+                    .find(|path| path.is_file())
+                    .ok_or_else(|| anyhow!("No list {view_id} in {:?}", dir))?;
+                Playlist::from_file(path)
+            },
+            ListSource::Archive(path) => {
+                let mut found = None;
+                for_each_list(path, |id, entry| {
+                    if id != view_id {
+                        return Ok(false);
+                    }
+
+                    let mut text = String::new();
+                    entry.read_to_string(&mut text).with_context(|| format!("List {id} is not valid UTF-8"))?;
+                    found = Some(text);
+                    Ok(true)
+                })?;
+
+                let text = found.ok_or_else(|| anyhow!("No list {view_id} in {:?}", path))?;
+                Playlist::parse(&text).with_context(|| format!("Unable to read list {view_id}"))
+            },
+        }
+    }
+
+    /// View ids of all lists in a local source, sorted
+    pub fn view_ids(&self) -> Result<Vec<String>> {
+        let mut ids = vec![];
+
+        match self {
+            ListSource::Server(host) => return Err(anyhow!("Lists on {host} can't be enumerated, use a directory or an archive")),
+            ListSource::Directory(dir) => {
+                for entry in std::fs::read_dir(dir).with_context(|| format!("Unable to read {:?}", dir))? {
+                    if let Some(id) = list_id(&entry?.path()) {
+                        ids.push(id);
+                    }
+                }
+            },
+            ListSource::Archive(path) => for_each_list(path, |id, _| {
+                ids.push(id);
+                Ok(false)
+            })?,
+        }
+
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+}
+
+/// API url for a list on a server given by host or base url
+pub fn list_url(host: &str, view_id: &str) -> String {
+    let root = if host.contains("://") { host.to_string() } else { format!("https://{host}") };
+    format!("{root}/api/list/{view_id}?type=xspf")
+}
+
+// View ids are used in paths and urls
+fn check_view_id(view_id: &str) -> Result<()> {
+    if !view_id.is_empty() && view_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid view id: {view_id:?}"))
+    }
+}
+
+// Hosts have a domain, a port or are localhost, and nothing after them
+fn is_host(spec: &str) -> bool {
+    let has_domain_or_port = spec.contains(['.', ':']) || spec.eq_ignore_ascii_case("localhost");
+    has_domain_or_port && url::Url::parse(&format!("https://{spec}"))
+        .is_ok_and(|url| url.host_str().is_some() && url.path() == "/" && url.query().is_none() && url.fragment().is_none())
+}
+
+fn is_list_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xspf") || ext.eq_ignore_ascii_case("jspf"))
+}
+
+// View id for list files like `<view-id>.xspf`
+fn list_id(path: &Path) -> Option<String> {
+    if !is_list_file(path) {
+        return None;
+    }
+
+    let id = path.file_stem()?.to_str()?;
+    check_view_id(id).ok().map(|_| id.to_string())
+}
+
+// Call `f` with the view id and contents of each list file in a tar archive
+// until it returns true. Entries are only read by `f`, so lists it isn't
+// interested in can't fail the walk.
+fn for_each_list(path: &Path, mut f: impl FnMut(String, &mut dyn Read) -> Result<bool>) -> Result<()> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("Unable to open {:?}", path))?;
+    let mut magic = [0u8; 2];
+    let gzipped = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+    let file = std::fs::File::open(path)?;
+
+    let reader: Box<dyn Read> = if gzipped { Box::new(flate2::read::GzDecoder::new(file)) } else { Box::new(file) };
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries().with_context(|| format!("Unable to read archive {:?}", path))? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let Some(id) = list_id(&entry.path()?) else {
+            continue;
+        };
+
+        if f(id, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XSPF: &str = r#"<playlist><title>Mix</title><trackList><track><title>A</title><creator>B</creator></track></trackList></playlist>"#;
+
+    #[test]
+    fn loads_local_sources() {
+        let dir = std::env::temp_dir().join(format!("mbzlists-source-{}", std::process::id()));
+        let lists = dir.join("lists");
+        std::fs::create_dir_all(&lists).unwrap();
+        std::fs::write(lists.join("abc-123.xspf"), XSPF).unwrap();
+        std::fs::write(lists.join("README.txt"), "not a list").unwrap();
+        std::fs::write(lists.join("binary.xspf"), [0xff, 0xfe, 0x00]).unwrap();
+
+        let archive_path = dir.join("lists.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&archive_path).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_dir_all("export", &lists).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        for spec in [&lists, &archive_path] {
+            let source = ListSource::parse(spec.to_str().unwrap()).unwrap();
+            assert!(source.is_local());
+            assert_eq!(source.view_ids().unwrap(), vec!["abc-123", "binary"]);
+            assert_eq!(source.load("abc-123").unwrap().title, "Mix");
+            assert!(source.load("missing").is_err());
+            assert!(source.load("../abc-123").is_err());
+        }
+
+        let err = ListSource::parse(lists.join("abc-123.xspf").to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("single list"), "{err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_servers() {
+        assert_eq!(ListSource::parse("mbzlists.com").unwrap(), ListSource::Server("mbzlists.com".to_string()));
+        assert_eq!(ListSource::parse("localhost:8000").unwrap(), ListSource::Server("localhost:8000".to_string()));
+        assert_eq!(ListSource::parse("http://localhost:8000/").unwrap(), ListSource::Server("http://localhost:8000".to_string()));
+
+        for spec in ["./missing-lists", "/missing/lists", "~/missing-lists", "missing-lists", "example.com/lists"] {
+            assert!(ListSource::parse(spec).is_err(), "{spec}");
+        }
+    }
+}
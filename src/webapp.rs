@@ -1,8 +1,14 @@
 use crate::platform::spotify;
 use crate::platform::youtube;
+use crate::platform::deezer;
+use crate::platform::tidal;
+use crate::platform::apple_music;
+use crate::{cache::ResolutionCache, matching::ArtistNames, mbzlists::Track, musicbrainz::MusicBrainzClient, overrides::Overrides};
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, get, http::StatusCode, App, HttpResponse, HttpServer, Responder};
+use actix_web::{cookie::Key, error, get, http::StatusCode, web, App, HttpResponse, HttpServer, Responder};
 use askama::Template;
+use std::sync::Arc;
+use log::warn;
 
 
 #[derive(Template)]
@@ -23,6 +29,94 @@ pub struct PlCreatedPageTemplate<'a> {
     pub playlist_url: &'a str,
 }
 
+// Where a track's platform id comes from in `resolve_tracks`
+enum Lookup {
+    /// Known from the overrides or the cache, `None` for a cached miss
+    Known(Option<String>),
+    /// Has to be searched, with the ISRCs and artist names to search with
+    Search(Vec<String>, ArtistNames),
+}
+
+/// Platform ids for `tracks`, taken from the overrides and the resolution
+/// cache where possible and searched with `resolve` otherwise. `resolve` gets
+/// the track with its ISRCs (looked up on MusicBrainz if `lookup_isrcs` is
+/// set) and artist names, and gives `None` when the search found no match.
+/// Only those misses are cached, other errors are logged and the track is
+/// left out.
+pub async fn resolve_tracks(
+    platform: &str,
+    cache_platform: &str,
+    mb_client: web::Data<MusicBrainzClient>,
+    tracks: Vec<Track>,
+    lookup_isrcs: bool,
+    resolve: impl AsyncFn(&Track, &[String], &ArtistNames) -> anyhow::Result<Option<String>>,
+) -> Result<Vec<String>, error::Error> {
+    // Overrides, the cache and MusicBrainz are all blocking, so everything
+    // but the platform searches runs off the async workers.
+    let (cache, lookups) = {
+        let (platform, cache_platform, tracks) = (platform.to_string(), cache_platform.to_string(), tracks.clone());
+        web::block(move || -> anyhow::Result<_> {
+            let overrides = Overrides::load_default()?;
+            let cache = ResolutionCache::open_default()
+                .inspect_err(|err| warn!("Resolution cache unavailable: {err}"))
+                .ok();
+
+            let lookups = tracks.iter().map(|track| {
+                if let Some(id) = overrides.lookup(&platform, track) {
+                    return Lookup::Known(Some(id));
+                }
+
+                if let Some(entry) = cache.as_ref().and_then(|c| c.get(&cache_platform, track).ok().flatten()) {
+                    return Lookup::Known(entry.platform_id);
+                }
+
+                let isrcs = track.recording_mbid()
+                    .filter(|_| lookup_isrcs)
+                    .and_then(|mbid| mb_client.recording(&mbid).ok())
+                    .map(|recording| recording.isrcs)
+                    .unwrap_or_default();
+                Lookup::Search(isrcs, ArtistNames::for_track(track, Some(&mb_client)))
+            }).collect::<Vec<Lookup>>();
+
+            Ok((cache, lookups))
+        }).await?.map_err(error::ErrorInternalServerError)?
+    };
+
+    let mut ids = Vec::new();
+    let mut resolutions = Vec::new();
+
+    for (track, lookup) in tracks.into_iter().zip(lookups) {
+        let (isrcs, artist_names) = match lookup {
+            Lookup::Known(id) => {
+                ids.extend(id);
+                continue;
+            },
+            Lookup::Search(isrcs, artist_names) => (isrcs, artist_names),
+        };
+
+        match resolve(&track, &isrcs, &artist_names).await {
+            Ok(resolved) => {
+                ids.extend(resolved.clone());
+                resolutions.push((track, resolved));
+            },
+            // Not cached since this might be a temporary failure
+            Err(err) => warn!("Search failed for {} - {}: {err}", track.creator, track.title),
+        }
+    }
+
+    if let Some(cache) = cache {
+        let cache_platform = cache_platform.to_string();
+        web::block(move || {
+            for (track, resolved) in resolutions {
+                if let Err(err) = cache.put(&cache_platform, &track, resolved.as_deref()) {
+                    warn!("Unable to write to resolution cache: {err}");
+                }
+            }
+        }).await?;
+    }
+
+    Ok(ids)
+}
 
 #[get("/")]
 async fn home() -> impl Responder {
@@ -33,13 +127,16 @@ async fn home() -> impl Responder {
         .body(body)
 }
 
-pub async fn serve() -> std::io::Result<()> {
+/// Serve the webapp. The MusicBrainz client is blocking, so it is created by
+/// the caller outside of the async runtime and only used in `web::block`.
+pub async fn serve(mb_client: Arc<MusicBrainzClient>) -> std::io::Result<()> {
     let secret_key = Key::generate();
     let host = std::env::var("MBZR_HOST").unwrap_or("127.0.0.1".to_string());
     let port = std::env::var("MBZR_PORT").unwrap_or("8888".to_string()).parse::<u16>().unwrap();
 
     HttpServer::new(move || {
         App::new()
+            .app_data(web::Data::from(mb_client.clone()))
             .wrap(SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone()).build())
             .service(home)
             .service(spotify::login)
@@ -48,6 +145,15 @@ pub async fn serve() -> std::io::Result<()> {
             .service(youtube::login)
             .service(youtube::callback)
             .service(youtube::create)
+            .service(deezer::login)
+            .service(deezer::callback)
+            .service(deezer::create)
+            .service(tidal::login)
+            .service(tidal::callback)
+            .service(tidal::create)
+            .service(apple_music::login)
+            .service(apple_music::callback)
+            .service(apple_music::create)
     })
     .bind((host, port))?
     .run()